@@ -1,39 +1,121 @@
-use std::{
-    path::PathBuf,
-    collections::HashMap,
-};
+use std::path::PathBuf;
 
 
 use serde::Serialize;
 
 
 ///Enum indicating the type of book.
-#[derive(Debug, Serialize, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Serialize, PartialEq)]
 pub enum BookType {
     MDBook,
     PDF,
 }
 
 
+///A downloadable file produced for a book, such as an EPUB from an extra mdBook renderer.
+#[derive(Debug, Clone, Serialize)]
+pub struct BookDownload {
+    pub format: String,
+    pub partial_path: PathBuf, //path isolated from the target directory
+    pub size: Option<u64>, //in bytes
+}
+
+
+///A link to one of the pages above the current one, used to build breadcrumb trails.
+#[derive(Debug, Clone, Serialize)]
+pub struct Breadcrumb {
+    pub name: String,
+    pub url: String,
+}
+
+
+///Where a book sits in a series, with links to the volumes either side of it.
+#[derive(Debug, Clone, Serialize)]
+pub struct SeriesPlacement {
+    pub name: String,
+    pub volume: usize, //position in the series counted from one
+    pub volumes: usize,
+    pub previous: Option<Breadcrumb>,
+    pub next: Option<Breadcrumb>,
+}
+
+
+///Which version of a logical book a book is, with links to every version of it.
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionPlacement {
+    pub name: String, //name of the logical book
+    pub version: String,
+    pub is_latest: bool,
+    
+    pub latest: Breadcrumb, //newest version, linked through its stable alias
+    pub versions: Vec<Breadcrumb>, //newest first
+    
+    pub alias_path: Option<PathBuf>, //path of the alias isolated from the target directory, none when a book already sits there
+    pub alias_page: PathBuf, //path isolated from the target directory of the page the alias opens
+}
+
+
+///Which translation of a logical book a book is, with links to every translation of it.
+#[derive(Debug, Clone, Serialize)]
+pub struct TranslationPlacement {
+    pub name: String, //name of the logical book
+    pub language: String,
+    pub is_default: bool, //whether the group's card opens this translation
+    pub languages: Vec<Breadcrumb>, //every translation, named by language
+    
+    pub shelf_path: PathBuf, //path isolated from src or target directory the group's card is placed at
+}
+
+
 ///Struct with data about a single book
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct BookMetadata {
     pub book_type: BookType,
     
     pub title: String,
     pub description: String,
+    pub authors: Vec<String>,
     
     pub source_path: PathBuf,
     pub partial_path: PathBuf, //path isolated from src or target directory
     pub build_path: PathBuf,
+    
+    pub link_path: PathBuf, //path isolated from target directory of the page the book's card opens
+    pub downloads: Vec<BookDownload>,
+    pub size: Option<u64>, //in bytes, for books that are a single file
+    pub pages: Option<usize>, //for PDFs
+    pub chapters: Option<usize>, //for mdbooks, not counting drafts
+    
+    pub modified: Option<u64>, //seconds since the unix epoch the book's source last changed
+    pub weight: Option<i64>,
+    pub tags: Vec<String>,
+    pub series: Option<SeriesPlacement>,
+    pub version: Option<VersionPlacement>,
+    pub language: Option<String>,
+    pub translation: Option<TranslationPlacement>,
+}
+
+
+///Volumes of a series placed on the same shelf, shown together as a single card.
+#[derive(Debug, Serialize)]
+pub struct BookSeries {
+    pub name: String,
+    pub books: Vec<BookMetadata>, //in reading order
 }
 
 #[derive(Debug, Default, Serialize)]
 //#[serde(untagged)]
 pub struct HierarchySection {
     pub name: String,
+    pub partial_path: PathBuf, //path of the shelf isolated from src or target directory
+    pub url: String, //page listing the shelf
+    pub weight: Option<i64>,
+    pub introduction: Option<String>, //html rendered from a README.md in the shelf directory
+    
     pub books: Vec<BookMetadata>,
-    pub sub_sections: HashMap<String, HierarchySection>,
+    pub series: Vec<BookSeries>,
+    pub sub_sections: Vec<HierarchySection>,
 }
 
 
@@ -45,5 +127,10 @@ pub struct BookshelfMetadata {
 
     pub book_hierarchy: HierarchySection,
     
+    ///virtual shelves, one per tag, listing every book carrying the tag
+    pub tag_shelves: Vec<HierarchySection>,
+    
+    ///languages translated books are available in
+    pub languages: Vec<String>,
 }
 
@@ -1,17 +1,36 @@
 use std::{
-    path::PathBuf,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
     fs,
+    rc::Rc,
+    time::UNIX_EPOCH,
 };
 
-use log;
-
 use mdbook::{
     MDBook,
-    //config::Config,
+    book::BookItem,
+    config::Config as BookConfig,
 };
+use walkdir::WalkDir;
+
 
+use crate::book::{BookType, BookMetadata, BookDownload, Breadcrumb, SeriesPlacement, VersionPlacement, TranslationPlacement};
+use crate::config::{Config, SandboxViolation, BookSettings};
+use crate::page_builder::shelf_url;
+use crate::page_builder::catalog::Catalog;
+use crate::page_builder::theme::{Theme, BOOK_NAV};
 
-use crate::book::{BookType, BookMetadata};
+use super::injector::{inject_theme, BookInjection, PATH_TO_ROOT};
+use super::overrides::{apply_shelf_config, apply_color_theme, apply_site_url};
+use super::preprocessors::{loaded_preprocessors, register_shelf_preprocessors};
+use super::sandbox::{load_book_config, strip_commands, SandboxReport};
+use super::shelf_links::{LinkReport, LinkTargets, ShelfLinkPreprocessor};
+use super::common::{copy_common_directory, resolve_common_alias};
+use super::series::{shelf_series, place_in_series, SeriesMember};
+use super::versions::{conventional_version, place_versions, create_latest_alias, VersionMember};
+use super::translations::{named_after_language, place_translations, TranslationMember};
+use super::pdf::page_count;
+use super::slugs::Slugs;
 
 
 ///strips out everything from path before the source folder. 
@@ -26,13 +45,13 @@ fn isolate_partial_path(full_path: &PathBuf, source_path: &PathBuf) -> Result<Pa
         let src_comp = source_iter.next();
         
         //strip out everything not matching the source path.
-        if src_comp != None {
-            if component != src_comp.unwrap() {
+        if let Some(src_comp) = src_comp {
+            if component != src_comp {
                 return Err(format!("Full path {:#?} does not match source path {:#?}. This should not be possible and is a bug. Terminating.", full_path, source_path).to_string());
             }
         
         //now that the source path is removed, everything else is added to our new path.
-        } else if src_comp == None {
+        } else {
             partial_path.push(component);
         }
     }
@@ -41,58 +60,449 @@ fn isolate_partial_path(full_path: &PathBuf, source_path: &PathBuf) -> Result<Pa
 }
 
 
-///Builds books and assembles a list of book metadata objects from the resulting info.
-pub fn build_books(books_index: Vec<(BookType, PathBuf)>, 
-             source_path: PathBuf, build_path: PathBuf, bookshelf_directory: PathBuf) -> Vec<BookMetadata> {
-    
-    let mut books_metadata = vec!();
+///File types produced by mdBook renderers that are offered as downloads on the book's card.
+const DOWNLOAD_EXTENSIONS: [&str; 3] = ["epub", "pdf", "mobi"];
+
+
+///Names of the renderers mdBook will run for the book. Must be given the config the book is
+/// loaded with since mdBook decides its renderers, and so its output layout, when loading.
+fn renderer_names(book_config: &BookConfig) -> Vec<String> {
+    match book_config.get("output").and_then(|output| output.as_table()) {
+        Some(table) if ! table.is_empty() => table.keys().cloned().collect(),
+        
+        //mdbook falls back to html when nothing is configured
+        _ => vec!("html".to_string()),
+    }
+}
+
+
+///Where the html output of a book ends up, relative to the bookshelf build directory.
+///Returns None when the book has no html renderer. Mirrors `MDBook::build_dir_for`, which can
+/// only be asked once the book is loaded.
+fn html_path(partial_path: &Path, renderers: &[String]) -> Option<PathBuf> {
+    if ! renderers.iter().any(|name| name == "html") {
+        None
+    } else if renderers.len() > 1 {
+        Some(partial_path.join("html").join(""))
+    } else {
+        Some(partial_path.to_path_buf())
+    }
+}
+
+
+///Collects the downloadable files written by every renderer other than html.
+fn find_downloads(md: &MDBook, renderers: &[String], bookshelf_build_path: &Path) -> Vec<BookDownload> {
+    let mut downloads = vec!();
     
-    //process books by either copying files or triggering MDBook builds
-    for book in books_index {
-        //just to be clear what's what
-        let book_type = book.0;
-        let mut book_source_path = book.1;
+    for renderer in renderers.iter().filter(|name| *name != "html") {
+        let renderer_dir = md.build_dir_for(renderer);
         
+        for entry in WalkDir::new(&renderer_dir).into_iter().filter_map(Result::ok) {
+            let extension = entry.path()
+                                 .extension()
+                                 .map(|ext| ext.to_string_lossy().to_lowercase())
+                                 .unwrap_or_default();
+            
+            if ! entry.file_type().is_file() || ! DOWNLOAD_EXTENSIONS.contains(&extension.as_str()) {
+                continue;
+            }
+            
+            match entry.path().strip_prefix(bookshelf_build_path) {
+                Ok(partial_path) => downloads.push( BookDownload {
+                    format: extension.to_uppercase(),
+                    partial_path: partial_path.to_path_buf(),
+                    size: entry.metadata().ok().map(|metadata| metadata.len()),
+                }),
+                Err(_) => log::warn!("Renderer {} wrote {} outside of the bookshelf, not linking it.", 
+                                     renderer, entry.path().display()),
+            }
+        }
+    }
+    
+    downloads
+}
+
+
+///Most recent modification time of anything under the path, in seconds since the unix epoch.
+fn last_modified(path: &Path) -> Option<u64> {
+    WalkDir::new(path).into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+        .max()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+}
+
+
+///A book found by the indexer that has been checked and is ready to build.
+struct PendingBook {
+    book_type: BookType,
+    source_path: PathBuf,
+    partial_path: PathBuf,
+    ///where the book goes in the bookshelf build directory, which is its partial path unless slugged
+    url_path: PathBuf,
+    build_path: PathBuf,
+    
+    title: String,
+    language: Option<String>,
+    settings: BookSettings,
+    
+    ///config vetted by the sandbox, only set for mdbooks
+    book_config: Option<BookConfig>,
+}
+
+
+///Works out where every book goes and vets mdbook configs against the sandbox policy.
+///Books the sandbox blocks are left out and recorded in the report.
+fn prepare_books(config: &Config, books_index: Vec<(BookType, PathBuf)>, source_path: &Path, 
+                 bookshelf_build_path: &Path, slugs: &Slugs, sandbox_report: &mut SandboxReport) -> Vec<PendingBook> {
+    
+    let mut pending = vec!();
+    
+    for (book_type, mut book_source_path) in books_index {
         //get the part of the path unique to the source directory. We use this same relative
         // path when placing stuff in the build dir.
-        let mut partial_path = isolate_partial_path(&book_source_path, &source_path).unwrap();
+        let mut partial_path = isolate_partial_path(&book_source_path, &source_path.to_path_buf()).unwrap();
 
         //location to place the book.
-        let mut book_build_path = build_path.join(&bookshelf_directory).join(&partial_path);
-        
-        //based on book type we build the book then return title and description metadata
-        let (title, description) = match book_type {
+        let mut url_path = slugs.url_path(&partial_path);
+        let mut book_build_path = bookshelf_build_path.join(&url_path);
         
+        let (book_config, title, language, settings) = match book_type {
             BookType::MDBook => {
                 //Really dumb but this is the only way I could find to add a trailing slash easily
                 //Need a trailing slash since the MDBook messes up the web template links otherwise
-                //Add it to all 3 just to be consistant.
+                //Add it to all of them just to be consistant.
                 book_source_path.push("");
                 book_build_path.push("");
                 partial_path.push("");
+                url_path.push("");
+                
+                //read the book.tomel ourselves so the sandbox can vet it before mdbook sets up any commands
+                let (mut book_config, disallowed) = load_book_config(&config.sandbox, &book_source_path)
+                    .expect("Unable to load the book config");
+                
+                if ! disallowed.is_empty() {
+                    if config.sandbox.on_violation == SandboxViolation::Skip {
+                        log::warn!("Skipping MDBook \"{}\" as it uses commands the sandbox does not allow.", partial_path.display());
+                        sandbox_report.blocked.push( (partial_path, disallowed) );
+                        continue;
+                    }
+                    
+                    log::warn!("Building MDBook \"{}\" without commands the sandbox does not allow.", partial_path.display());
+                    strip_commands(&mut book_config, &disallowed);
+                    sandbox_report.downgraded.push( (partial_path.clone(), disallowed) );
+                }
+                
+                //series need to be known before any book is built so read the settings up front
+                let title = book_config.book.title.clone().unwrap_or_default();
+                let settings = book_config.get_deserialized_opt::<BookSettings, _>("bookshelf")
+                    .unwrap_or_else(|err| {
+                        log::error!("Ignoring invalid [bookshelf] settings of \"{}\": {:#}", partial_path.display(), err);
+                        None
+                    })
+                    .unwrap_or_default();
+                
+                let language = book_config.book.language.clone();
+                
+                (Some(book_config), title, language, settings)
+            },
+            BookType::PDF => {
+                let title = book_source_path.file_stem().unwrap().to_os_string().into_string().unwrap();
                 
+                let settings = BookSettings::load_sidecar(&book_source_path);
+                
+                (None, title, settings.language.clone(), settings)
+            },
+        };
+        
+        pending.push( PendingBook {
+            book_type,
+            source_path: book_source_path,
+            partial_path,
+            url_path,
+            build_path: book_build_path,
+            title,
+            language,
+            settings,
+            book_config,
+        });
+    }
+    
+    pending
+}
+
+
+///Collects the page each book's links should open so books can link to one another.
+fn link_targets(pending: &[PendingBook]) -> LinkTargets {
+    let mut targets = LinkTargets::new();
+    
+    for book in pending {
+        let page = match &book.book_config {
+            Some(book_config) => html_path(&book.url_path, &renderer_names(book_config)),
+            None => Some(book.url_path.clone()),
+        };
+        
+        if let Some(page) = page {
+            let key = book.partial_path.to_string_lossy().trim_end_matches('/').to_string();
+            targets.insert(key, page);
+        }
+    }
+    
+    targets
+}
+
+
+///Works out the series every book is part of. A series listed in a shelf.toml takes precedence
+/// over the one a book declares itself, the same as weights.
+fn series_placements(pending: &[PendingBook], targets: &LinkTargets, bookshelf_directory: &Path) -> HashMap<PathBuf, SeriesPlacement> {
+    let mut shelf_configs = HashMap::new();
+    
+    let members = pending.iter()
+        .filter_map(|book| {
+            let (series, volume) = match shelf_series(&book.source_path, &mut shelf_configs) {
+                Some( (series, volume) ) => (series, Some(volume)),
+                None => (book.settings.series.clone()?, book.settings.volume),
+            };
+            
+            //volumes link to the same page their cards open
+            let key = book.partial_path.to_string_lossy().trim_end_matches('/').to_string();
+            let page = targets.get(&key).cloned().unwrap_or_else(|| book.url_path.clone());
+            
+            Some( SeriesMember {
+                partial_path: book.partial_path.clone(),
+                title: book.title.clone(),
+                url: format!("{}/{}", bookshelf_directory.display(), page.display()),
+                series, volume,
+            })
+        })
+        .collect();
+    
+    place_in_series(members)
+}
+
+
+///Works out which books are versions of the same logical book. Books declare this in their settings
+/// or follow the `<name>-v<version>` naming convention.
+fn version_placements(pending: &[PendingBook], targets: &LinkTargets, bookshelf_directory: &Path, slugs: &Slugs) -> HashMap<PathBuf, VersionPlacement> {
+    let members = pending.iter()
+        .filter_map(|book| {
+            let book_name = book.partial_path.file_name()?.to_string_lossy().to_string();
+            
+            let (name, version, declared) = match &book.settings.version_of {
+                Some(name) => {
+                    let version = book.settings.version.clone()
+                        .or_else(|| conventional_version(&book_name).map(|(_, version)| version))
+                        .unwrap_or(book_name);
+                    (name.clone(), version, true)
+                },
+                None => {
+                    let (name, version) = conventional_version(&book_name)?;
+                    (name, version, false)
+                },
+            };
+            
+            let key = book.partial_path.to_string_lossy().trim_end_matches('/').to_string();
+            let page = targets.get(&key).cloned().unwrap_or_else(|| book.url_path.clone());
+            
+            Some( (VersionMember { partial_path: book.partial_path.clone(), url_path: book.url_path.clone(), name, version, page }, declared) )
+        })
+        .collect();
+    
+    //the latest alias must not take the place of a book or of a shelf holding books
+    let taken_paths: HashSet<PathBuf> = pending.iter()
+        .flat_map(|book| book.url_path.ancestors().filter(|path| ! path.as_os_str().is_empty()))
+        .map(|path| path.components().collect())
+        .collect();
+    
+    place_versions(members, &taken_paths, bookshelf_directory, slugs)
+}
+
+
+///Works out which books are translations of the same logical book. Books declare this in their
+/// settings or are named after their language inside the logical book's directory, ie `guide/de/`.
+fn translation_placements(pending: &[PendingBook], targets: &LinkTargets, default_language: &str, 
+                          bookshelf_directory: &Path) -> HashMap<PathBuf, TranslationPlacement> {
+    let members = pending.iter()
+        .filter_map(|book| {
+            let declared_name = book.settings.translation_of.clone();
+            
+            let language = match (&book.language, &declared_name) {
+                (Some(language), _) if declared_name.is_some() || named_after_language(&book.partial_path, language) => language.clone(),
+                //a declared translation without a language is named after it
+                (None, Some(_)) => book.partial_path.file_stem()?.to_string_lossy().to_string(),
+                _ => return None,
+            };
+            
+            let key = book.partial_path.to_string_lossy().trim_end_matches('/').to_string();
+            let page = targets.get(&key).cloned().unwrap_or_else(|| book.url_path.clone());
+            
+            Some( TranslationMember { partial_path: book.partial_path.clone(), language, declared_name, page } )
+        })
+        .collect();
+    
+    place_translations(members, default_language, bookshelf_directory)
+}
+
+
+///Builds books and assembles a list of book metadata objects from the resulting info.
+#[allow(clippy::too_many_arguments)]
+pub fn build_books(config: &Config, theme: &Theme, catalog: &Catalog, books_index: Vec<(BookType, PathBuf)>, slugs: &Slugs,
+             source_path: PathBuf, build_path: PathBuf, bookshelf_directory: PathBuf) -> Vec<BookMetadata> {
+    
+    let mut books_metadata = vec!();
+    
+    let bookshelf_build_path = build_path.join(&bookshelf_directory);
+    
+    //theme overrides injected into mdbooks are written here so book sources are left untouched
+    //a private directory of its own, removed once every book is built
+    let staging = tempfile::Builder::new().prefix("mdbookshelf-").tempdir()
+        .expect("Unable to create a temporary theme directory");
+    let staging_path = staging.path();
+    
+    //books that asked to run commands the sandbox policy does not allow
+    let mut sandbox_report = SandboxReport::default();
+    
+    //shelf links that lead nowhere, from every book
+    let link_report = LinkReport::default();
+    
+    let pending = prepare_books(config, books_index, &source_path, &bookshelf_build_path, slugs, &mut sandbox_report);
+    let targets = Rc::new(link_targets(&pending));
+    let mut placements = series_placements(&pending, &targets, &bookshelf_directory);
+    let mut versions = version_placements(&pending, &targets, &bookshelf_directory, slugs);
+    let mut translations = translation_placements(&pending, &targets, &config.bookshelf.language, &bookshelf_directory);
+    let catalog = Rc::new(catalog.clone());
+    let navigation = Rc::new(theme.text(BOOK_NAV.0));
+    
+    //files every book can include or link to, copied into the build directory once
+    let common_path = config.bookshelf.common_directory.as_ref().map(|dir| config.root.join(dir));
+    match &common_path {
+        Some(common_path) if common_path.is_dir() => copy_common_directory(common_path, &build_path),
+        Some(common_path) => log::warn!("Shared directory {} does not exist.", common_path.display()),
+        None => (),
+    }
+    
+    //how many directories the bookshelf directory is below the build directory
+    let bookshelf_depth = bookshelf_directory.components().count();
+    
+    //process books by either copying files or triggering MDBook builds
+    for book in pending {
+        //just to be clear what's what
+        let PendingBook { book_type, source_path: book_source_path, partial_path, url_path, build_path: book_build_path, 
+                          title, language, settings, book_config } = book;
+        
+        let series = placements.remove(&partial_path);
+        let version = versions.remove(&partial_path);
+        let translation = translations.remove(&partial_path);
+        
+        //based on book type we build the book then return title and description metadata along with
+        // the page the book's card should open and any extra files to offer for download
+        let (title, description, authors, link_path, downloads, chapters) = match book_config {
+        
+            Some(book_config) => {
                 log::info!("Bulding MDBook \"{}\"", partial_path.display());
                 log::debug!("> MDBook source {}, building into {}\n", 
                             book_source_path.display(), book_build_path.display());
                 
-                //create book object from path which has the book.tomel and all needed info
-                let mut md = MDBook::load(&book_source_path)
+                let renderers = renderer_names(&book_config);
+                
+                //create book object from path which has all needed info
+                let mut md = MDBook::load_with_config(&book_source_path, book_config)
                     .expect("Unable to load the book");
                 
+                let preprocessors = loaded_preprocessors(&md);
+                let book_html_path = html_path(&url_path, &renderers).unwrap_or_else(|| url_path.clone());
+                
+                //point the shared directory alias at the real files before any preprocessor runs
+                if let Some(common_path) = &common_path {
+                    resolve_common_alias(&mut md.book, common_path, bookshelf_depth + book_html_path.components().count());
+                }
+                
                 //we need to set the output to be inside the books individual build directory 
                 md.config.build.build_dir = book_build_path.clone();
                 
+                //shelf wide mdbook settings take precedence over the book's own
+                let inlined = apply_shelf_config(&mut md, &config.mdbook, &config.root, &partial_path);
+                apply_color_theme(&mut md, config.bookshelf.color_theme, &config.mdbook);
+                
+                //links in the navigation climb from each page to the book's html directory, then out to the site root
+                let book_depth = bookshelf_depth + book_html_path.components().count();
+                let site_root = if config.bookshelf.relative_links {
+                    format!("{}{}", PATH_TO_ROOT, "../".repeat(book_depth))
+                } else {
+                    apply_site_url(&mut md, &format!("{}{}/{}", config.bookshelf.site_root(), bookshelf_directory.display(), book_html_path.display()), &config.mdbook);
+                    config.bookshelf.site_root()
+                };
+                register_shelf_preprocessors(&mut md, &config.mdbook, &preprocessors);
+                
+                //resolve shelf: links last so links pulled in by other preprocessors are caught too
+                md.with_preprocessor(ShelfLinkPreprocessor::new(targets.clone(), link_report.clone(), partial_path.display().to_string(),
+                                                             config.bookshelf.strict_shelf_links, book_html_path.clone()));
+                
+                //add the navigation bar that leads back to the bookshelf
+                let mut shelves = vec!();
+                let mut shelf_path = PathBuf::new();
+                //translations named after their language sit in the logical book's directory, which isn't a shelf
+                let shelf_dirs = match &translation {
+                    Some(translation) => translation.shelf_path.parent(),
+                    None => partial_path.parent(),
+                };
+                if let Some(shelf_dirs) = shelf_dirs {
+                    for comp in shelf_dirs.components() {
+                        shelf_path.push(comp);
+                        shelves.push( Breadcrumb {
+                            name: comp.as_os_str().to_string_lossy().to_string(),
+                            url: shelf_url(&slugs.url_path(&shelf_path)),
+                        });
+                    }
+                }
+                
+                let injection = BookInjection {
+                    site_title: config.bookshelf.title.clone(),
+                    index_url: String::new(),
+                    site_root,
+                    relative_links: config.bookshelf.relative_links,
+                    shelves,
+                    series: series.clone(),
+                    version: version.clone(),
+                    translation: translation.clone(),
+                    catalog: catalog.clone(),
+                    navigation: navigation.clone(),
+                    color_theme: config.bookshelf.color_theme,
+                    inlined,
+                };
+                
+                if let Err(err) = inject_theme(&mut md, &injection, &staging_path.join(&partial_path)) {
+                    log::error!("Unable to inject bookshelf navigation into \"{}\": {:#}", partial_path.display(), err);
+                }
+                
                 //Try to build the book
                 md.build().expect("Building failed");
                 
                 //pull some data from the mdbook config
-                let title = md.config.book.title.expect("MDBook missing title somehow.");
-                let description = md.config.book.description.unwrap_or("".to_string()).clone();
+                let title = md.config.book.title.clone().expect("MDBook missing title somehow.");
+                let description = md.config.book.description.clone().unwrap_or("".to_string());
+                let authors = md.config.book.authors.clone();
+                let chapters = md.book.iter()
+                                      .filter(|item| matches!(item, BookItem::Chapter(chapter) if chapter.path.is_some()))
+                                      .count();
+                
+                //with more than one renderer each writes into its own sub-directory
+                let downloads = find_downloads(&md, &renderers, &bookshelf_build_path);
+                
+                let link_path = if renderers.iter().any(|name| name == "html") {
+                    book_html_path
+                } else if let Some(download) = downloads.first() {
+                    download.partial_path.clone()
+                } else {
+                    log::warn!("MDBook \"{}\" has no html output or downloadable files to link to.", partial_path.display());
+                    url_path.clone()
+                };
                 
-                (title, description)
+                (title, description, authors, link_path, downloads, Some(chapters))
             },
         
-            BookType::PDF => {
+            None => {
                 log::info!("Bulding PDF \"{}\"", partial_path.display());
                 log::debug!("> PDF source {}, copying into {}\n", book_source_path.display(), book_build_path.display());
                 
@@ -108,25 +518,68 @@ pub fn build_books(books_index: Vec<(BookType, PathBuf)>,
                 if let Result::Err(err) = fs::copy(&book_source_path, &book_build_path) {
                     log::error!("{:#?}", err);
                 }
-                    
-                let title = book_source_path.file_stem().unwrap().to_os_string().into_string().unwrap();
 
-                ( title, "".to_string() )
+                ( title, "".to_string(), vec!(), url_path.clone(), vec!(), None )
+            },
+        };
+        
+        //the newest version is also copied to a stable alias that its card links to
+        let link_path = match &version {
+            Some(version) if version.is_latest => {
+                if let Some(alias_path) = &version.alias_path {
+                    create_latest_alias(&book_build_path, &bookshelf_build_path.join(alias_path));
+                }
+                version.alias_page.clone()
             },
+            _ => link_path,
+        };
+        
+        let modified = last_modified(&book_source_path);
+        let (size, pages) = match book_type {
+            BookType::PDF => ( fs::metadata(&book_build_path).ok().map(|metadata| metadata.len()), page_count(&book_source_path) ),
+            BookType::MDBook => (None, None),
+        };
+        
+        //a group of translations is placed on the shelf as the logical book they translate
+        let partial_path = match &translation {
+            Some(translation) if translation.is_default => translation.shelf_path.clone(),
+            _ => partial_path,
         };
         
         //create metadata object we will need to populate the index template
         books_metadata.push(
             BookMetadata {
                 book_type,
-                title, description,
+                title, description, authors,
                 
                 source_path: book_source_path,
                 partial_path,
                 build_path: book_build_path,
+                
+                link_path, downloads, size,
+                pages, chapters,
+                modified,
+                weight: settings.weight,
+                tags: settings.tags,
+                series,
+                version,
+                language,
+                translation,
             }
         );
     }
     
+    //older versions and other translations are reached through the links on the card of the group
+    books_metadata.retain(|book| book.version.as_ref().map(|version| version.is_latest).unwrap_or(true) &&
+                                 book.translation.as_ref().map(|translation| translation.is_default).unwrap_or(true));
+    
+    sandbox_report.log();
+    link_report.log();
+    
+    let staging_path = staging_path.to_path_buf();
+    if let Result::Err(err) = staging.close() {
+        log::warn!("Unable to remove temporary theme directory {}: {}", staging_path.display(), err);
+    }
+    
     books_metadata
 }
@@ -0,0 +1,93 @@
+use std::path::{Component, Path};
+
+use mdbook::book::{Book, BookItem};
+
+use super::injector::copy_dir_all;
+
+
+///Alias books use to refer to the shared directory, ie `{{#include @common/licence.md}}`.
+pub const COMMON_ALIAS: &str = "@common/";
+
+///Name of the directory, inside the build directory, the shared files are copied to.
+pub const COMMON_BUILD_DIRECTORY: &str = "common";
+
+///mdBook directives that read files from disk. The alias is replaced with the shared directory's
+/// real location in these, and with a url to the copied files everywhere else.
+const FILE_DIRECTIVES: [&str; 3] = ["include", "rustdoc_include", "playground"];
+
+
+///Copies the shared directory into the build directory once for every book to use.
+pub fn copy_common_directory(common_path: &Path, build_path: &Path) {
+    log::info!("Copying shared directory {}", common_path.display());
+
+    if let Err(err) = copy_dir_all(common_path, &build_path.join(COMMON_BUILD_DIRECTORY)) {
+        log::error!("Unable to copy the shared directory: {:#}", err);
+    }
+}
+
+
+///Checks if the alias at the given index sits inside a directive that reads a file.
+fn in_file_directive(line: &str, index: usize) -> bool {
+    let before = &line[..index];
+
+    match before.rfind("{{#") {
+        Some(open) if ! before[open..].contains("}}") => {
+            let name = before[open + 3..].split_whitespace().next().unwrap_or_default();
+            FILE_DIRECTIVES.contains(&name)
+        },
+        _ => false,
+    }
+}
+
+
+///Replaces the shared directory alias in every chapter of a freshly loaded book, before any
+/// preprocessor gets to see it. `root_depth` is how many directories the book's html output is
+/// below the build directory.
+pub fn resolve_common_alias(book: &mut Book, common_path: &Path, root_depth: usize) {
+    let mut common_dir = common_path.to_string_lossy().to_string();
+    common_dir.push('/');
+
+    book.for_each_mut(|item| {
+        let chapter = match item {
+            BookItem::Chapter(chapter) if chapter.content.contains(COMMON_ALIAS) => chapter,
+            _ => return,
+        };
+
+        //chapters in sub-directories need to climb further to reach the build directory
+        let chapter_depth = chapter.path.as_ref()
+            .and_then(|path| path.parent())
+            .map(|dir| dir.components().filter(|comp| matches!(comp, Component::Normal(_))).count())
+            .unwrap_or(0);
+        let common_url = format!("{}{}/", "../".repeat(root_depth + chapter_depth), COMMON_BUILD_DIRECTORY);
+
+        let mut in_code_block = false;
+        chapter.content = chapter.content.split_inclusive('\n')
+            .map(|line| {
+                let trimmed = line.trim_start();
+                if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                    in_code_block = ! in_code_block;
+                }
+
+                let mut output = String::with_capacity(line.len());
+                let mut last = 0;
+
+                for (index, _) in line.match_indices(COMMON_ALIAS) {
+                    output.push_str(&line[last..index]);
+
+                    //directives are expanded even inside code blocks, urls are left alone there
+                    if in_file_directive(line, index) {
+                        output.push_str(&common_dir);
+                    } else if ! in_code_block {
+                        output.push_str(&common_url);
+                    } else {
+                        output.push_str(COMMON_ALIAS);
+                    }
+                    last = index + COMMON_ALIAS.len();
+                }
+
+                output.push_str(&line[last..]);
+                output
+            })
+            .collect();
+    });
+}
@@ -1,11 +1,27 @@
 
-use crate::book::{BookMetadata, HierarchySection};
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fs,
+    path::Path,
+};
 
+use mdbook::utils::render_markdown;
 
+use crate::book::{BookMetadata, BookSeries, HierarchySection};
+use crate::config::{ShelfConfig, SortKey};
+use crate::page_builder::{shelf_url, tag_url};
 
-pub fn compile_hierarchy(books_metadata: Vec<BookMetadata>) -> HierarchySection {
+use super::slugs::Slugs;
+
+
+
+pub fn compile_hierarchy(books_metadata: Vec<BookMetadata>, source_path: &Path, default_sort: SortKey, slugs: &Slugs) -> HierarchySection {
     
-    let mut book_sort = HierarchySection::default();
+    let mut book_sort = HierarchySection {
+        url: String::new(),
+        ..Default::default()
+    };
     
     //process each book
     for metadata in books_metadata {
@@ -23,18 +39,25 @@ pub fn compile_hierarchy(books_metadata: Vec<BookMetadata>) -> HierarchySection
         components.pop();
         
         for level in components {
-                
+            
             //create shelf entry if this is the first time we have seen this shelf
-            if ! current_container.sub_sections.contains_key(&level) {
-                let mut new_section = HierarchySection::default();
-                new_section.name = level.clone();
-                current_container.sub_sections.insert( level.clone(), new_section );
-            }
-                
+            let index = match current_container.sub_sections.iter().position(|section| section.name == level) {
+                Some(index) => index,
+                None => {
+                    let partial_path = current_container.partial_path.join(&level);
+                    let new_section = HierarchySection {
+                        name: level.clone(),
+                        url: shelf_url(&slugs.url_path(&partial_path)),
+                        partial_path,
+                        ..Default::default()
+                    };
+                    current_container.sub_sections.push( new_section );
+                    current_container.sub_sections.len() - 1
+                },
+            };
                 
             //shift the level down one
-            //Note: the above check ensures this unwrap will work
-            current_container = current_container.sub_sections.get_mut(&level).unwrap(); 
+            current_container = &mut current_container.sub_sections[index]; 
                 
         }
         
@@ -42,5 +65,336 @@ pub fn compile_hierarchy(books_metadata: Vec<BookMetadata>) -> HierarchySection
         current_container.books.push( metadata );
     }
     
+    sort_section(&mut book_sort, source_path, default_sort);
+    add_introductions(&mut book_sort, source_path);
+    
     book_sort
 }
+
+
+///File names checked, in order, for a shelf's introduction.
+const INTRODUCTION_FILES: [&str; 2] = ["README.md", "readme.md"];
+
+
+///Renders the README.md of every shelf directory, including the bookshelf directory itself,
+/// so it can be shown at the top of the shelf.
+fn add_introductions(section: &mut HierarchySection, source_path: &Path) {
+    let shelf_dir = source_path.join(&section.partial_path);
+    
+    let readme = INTRODUCTION_FILES.iter()
+                                   .map(|name| shelf_dir.join(name))
+                                   .find(|path| path.is_file());
+    
+    if let Some(readme) = readme {
+        match fs::read_to_string(&readme) {
+            Ok(markdown) => section.introduction = Some(render_markdown(&markdown, false)),
+            Err(err) => log::error!("Unable to read shelf introduction {}: {}", readme.display(), err),
+        }
+    }
+    
+    for sub_section in section.sub_sections.iter_mut() {
+        add_introductions(sub_section, source_path);
+    }
+}
+
+
+///Name of the file or directory a book was found at.
+fn book_name(book: &BookMetadata) -> String {
+    book.partial_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+
+///Most recent modification of any book on the shelf or its sub-shelves.
+fn section_modified(section: &HierarchySection) -> Option<u64> {
+    section.books.iter()
+        .chain(section.series.iter().flat_map(|series| series.books.iter()))
+        .filter_map(|book| book.modified)
+        .chain(section.sub_sections.iter().filter_map(section_modified))
+        .max()
+}
+
+
+///Compares names so runs of digits are compared by value, "Part 9" before "Part 10".
+///Everything else is compared ignoring case.
+pub(super) fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            
+            (Some(a_char), Some(b_char)) if a_char.is_ascii_digit() && b_char.is_ascii_digit() => {
+                let mut a_number = String::new();
+                while let Some(digit) = a_chars.next_if(char::is_ascii_digit) { a_number.push(digit); }
+                let mut b_number = String::new();
+                while let Some(digit) = b_chars.next_if(char::is_ascii_digit) { b_number.push(digit); }
+
+                //compare by value without parsing so long numbers can't overflow
+                let a_number = a_number.trim_start_matches('0');
+                let b_number = b_number.trim_start_matches('0');
+                let order = a_number.len().cmp(&b_number.len()).then_with(|| a_number.cmp(b_number));
+                if order != Ordering::Equal {
+                    return order;
+                }
+            },
+            
+            (Some(a_char), Some(b_char)) => {
+                let order = a_char.to_lowercase().cmp(b_char.to_lowercase());
+                if order != Ordering::Equal {
+                    return order;
+                }
+                a_chars.next();
+                b_chars.next();
+            },
+        }
+    }
+}
+
+
+///Orders two entries by the sort key, falling back to their names so the order is always stable.
+fn compare(sort: SortKey, names: (&str, &str), titles: (&str, &str), 
+           modified: (Option<u64>, Option<u64>), weights: (Option<i64>, Option<i64>)) -> Ordering {
+    let by_name = natural_cmp(names.0, names.1).then_with(|| names.0.cmp(names.1));
+
+    let order = match sort {
+        SortKey::Name => names.0.to_lowercase().cmp(&names.1.to_lowercase()),
+        SortKey::Natural => Ordering::Equal,
+        SortKey::Title => natural_cmp(titles.0, titles.1),
+        SortKey::Date => modified.1.cmp(&modified.0),
+        //unweighted entries go after weighted ones
+        SortKey::Weight => match weights {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        },
+    };
+
+    order.then(by_name)
+}
+
+
+///Sorts the books and sub-shelves of a section, and all sections below it. A shelf.toml in the
+/// shelf's directory can change the sort key, which its sub-shelves then inherit.
+fn sort_section(section: &mut HierarchySection, source_path: &Path, inherited_sort: SortKey) {
+    //the root section is the bookshelf directory itself, its shelf.toml configures the root shelf
+    // like any other, as it does for series
+    let shelf_config = ShelfConfig::load(&source_path.join(&section.partial_path));
+    let sort = shelf_config.sort.unwrap_or(inherited_sort);
+    
+    if shelf_config.weight.is_some() {
+        section.weight = shelf_config.weight;
+    }
+
+    //weights from this shelf's config override those set by the entries themselves
+    for book in section.books.iter_mut() {
+        if let Some(weight) = shelf_config.weights.get(&book_name(book)) {
+            book.weight = Some(*weight);
+        }
+    }
+
+    for sub_section in section.sub_sections.iter_mut() {
+        sort_section(sub_section, source_path, sort);
+        
+        if let Some(weight) = shelf_config.weights.get(&sub_section.name) {
+            sub_section.weight = Some(*weight);
+        }
+    }
+
+    section.books.sort_by(|a, b| {
+        compare(sort, (&book_name(a), &book_name(b)), (&a.title, &b.title), (a.modified, b.modified), (a.weight, b.weight))
+    });
+
+    section.sub_sections.sort_by(|a, b| {
+        compare(sort, (&a.name, &b.name), (&a.name, &b.name), (section_modified(a), section_modified(b)), (a.weight, b.weight))
+    });
+    
+    group_series(section);
+}
+
+
+///Moves the volumes of each series on the shelf onto a single series card, in reading order.
+///Series are ordered by wherever their first volume landed when the books were sorted.
+fn group_series(section: &mut HierarchySection) {
+    let (volumes, books): (Vec<BookMetadata>, Vec<BookMetadata>) = std::mem::take(&mut section.books)
+        .into_iter()
+        .partition(|book| book.series.is_some());
+    section.books = books;
+    
+    for book in volumes {
+        let name = book.series.as_ref().map(|series| series.name.clone()).unwrap_or_default();
+        
+        match section.series.iter_mut().find(|series| series.name == name) {
+            Some(series) => series.books.push(book),
+            None => section.series.push( BookSeries { name, books: vec!(book) } ),
+        }
+    }
+    
+    for series in section.series.iter_mut() {
+        series.books.sort_by_key(|book| book.series.as_ref().map(|series| series.volume));
+    }
+}
+
+
+///Gathers every book below the section, including those on its sub-shelves.
+fn collect_books<'a>(section: &'a HierarchySection, books: &mut Vec<&'a BookMetadata>) {
+    books.extend(section.books.iter());
+    books.extend(section.series.iter().flat_map(|series| series.books.iter()));
+    
+    for sub_section in &section.sub_sections {
+        collect_books(sub_section, books);
+    }
+}
+
+
+///Builds a virtual shelf for every tag used by any book, holding every book carrying it.
+///The books come from many shelves, so they are ordered by the bookshelf's sort key rather than any shelf's.
+pub fn compile_tag_shelves(hierarchy: &HierarchySection, sort: SortKey, slugs: &Slugs) -> Vec<HierarchySection> {
+    let mut books = vec!();
+    collect_books(hierarchy, &mut books);
+    
+    let mut tags: BTreeMap<&str, Vec<BookMetadata>> = BTreeMap::new();
+    for book in books {
+        for tag in &book.tags {
+            tags.entry(tag.as_str()).or_default().push(book.clone());
+        }
+    }
+    
+    let directories = slugs.names(tags.keys().copied());
+    
+    let mut tag_shelves: Vec<HierarchySection> = tags.into_iter()
+        .filter_map(|(tag, mut books)| {
+            //tags come from any book, and one like `..` would have its page written over another
+            let directory = match directories.get(tag) {
+                Some(directory) => directory,
+                None => {
+                    log::error!("The tag \"{}\" can't be used as a directory name, leaving out its page.", tag);
+                    return None;
+                },
+            };
+            
+            books.sort_by(|a, b| {
+                compare(sort, (&book_name(a), &book_name(b)), (&a.title, &b.title), (a.modified, b.modified), (a.weight, b.weight))
+            });
+            
+            Some( HierarchySection {
+                name: tag.to_string(),
+                url: tag_url(directory),
+                books,
+                ..Default::default()
+            })
+        })
+        .collect();
+    
+    tag_shelves.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+    
+    tag_shelves
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::book::BookType;
+
+
+    fn book(partial_path: &str, tags: &[&str]) -> BookMetadata {
+        BookMetadata {
+            book_type: BookType::PDF,
+            title: partial_path.to_string(),
+            description: String::new(),
+            authors: vec!(),
+            source_path: PathBuf::from(partial_path),
+            partial_path: PathBuf::from(partial_path),
+            build_path: PathBuf::from(partial_path),
+            link_path: PathBuf::from(partial_path),
+            downloads: vec!(),
+            size: None,
+            pages: None,
+            chapters: None,
+            modified: None,
+            weight: None,
+            tags: tags.iter().map(ToString::to_string).collect(),
+            series: None,
+            version: None,
+            language: None,
+            translation: None,
+        }
+    }
+
+
+    fn sorted(mut names: Vec<&str>) -> Vec<&str> {
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+    }
+
+
+    #[test]
+    fn natural_cmp_compares_numbers_by_value() {
+        assert_eq!(sorted(vec!("Part 10", "Part 9", "Part 1", "Part 100")), ["Part 1", "Part 9", "Part 10", "Part 100"]);
+        assert_eq!(sorted(vec!("v1.10", "v1.2", "v1.9")), ["v1.2", "v1.9", "v1.10"]);
+        assert_eq!(natural_cmp("Part 007", "Part 7"), Ordering::Equal);
+        assert_eq!(natural_cmp("99999999999999999999999", "100000000000000000000000"), Ordering::Less);
+    }
+
+
+    #[test]
+    fn natural_cmp_ignores_case() {
+        assert_eq!(natural_cmp("alpha", "Alpha"), Ordering::Equal);
+        assert_eq!(sorted(vec!("beta", "Alpha", "gamma")), ["Alpha", "beta", "gamma"]);
+        assert_eq!(natural_cmp("Part", "Part 1"), Ordering::Less);
+    }
+
+
+    #[test]
+    fn compare_falls_back_to_names() {
+        let weighed = |a: Option<i64>, b: Option<i64>| compare(SortKey::Weight, ("b", "a"), ("b", "a"), (None, None), (a, b));
+
+        assert_eq!(weighed(Some(1), Some(2)), Ordering::Less);
+        assert_eq!(weighed(Some(5), None), Ordering::Less);
+        assert_eq!(weighed(None, Some(5)), Ordering::Greater);
+        assert_eq!(weighed(Some(1), Some(1)), Ordering::Greater);
+
+        //names differing only in case still have a fixed order
+        assert_ne!(compare(SortKey::Natural, ("a", "A"), ("a", "A"), (None, None), (None, None)), Ordering::Equal);
+        assert_eq!(compare(SortKey::Date, ("a", "b"), ("a", "b"), (Some(1), Some(2)), (None, None)), Ordering::Greater);
+    }
+
+
+    #[test]
+    fn tag_shelves_gather_books_from_every_shelf() {
+        let hierarchy = HierarchySection {
+            books: vec!(book("b.pdf", &["rust"])),
+            sub_sections: vec!( HierarchySection { books: vec!(book("shelf/a.pdf", &["rust", "C#"])), ..Default::default() } ),
+            ..Default::default()
+        };
+
+        let tag_shelves = compile_tag_shelves(&hierarchy, SortKey::Natural, &Slugs::default());
+
+        let names: Vec<(&str, &str)> = tag_shelves.iter().map(|shelf| (shelf.name.as_str(), shelf.url.as_str())).collect();
+        assert_eq!(names, [("C#", "tags/C-/"), ("rust", "tags/rust/")]);
+
+        let books: Vec<&Path> = tag_shelves[1].books.iter().map(|book| book.partial_path.as_path()).collect();
+        assert_eq!(books, [Path::new("shelf/a.pdf"), Path::new("b.pdf")]);
+    }
+
+
+    #[test]
+    fn tag_shelves_leave_out_tags_that_are_not_directories() {
+        let hierarchy = HierarchySection { books: vec!(book("a.pdf", &["..", ".", "", "ok"])), ..Default::default() };
+
+        let tag_shelves = compile_tag_shelves(&hierarchy, SortKey::Natural, &Slugs::default());
+
+        let names: Vec<&str> = tag_shelves.iter().map(|shelf| shelf.name.as_str()).collect();
+        assert_eq!(names, ["ok"]);
+    }
+}
@@ -1,5 +1,5 @@
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use walkdir::{
     WalkDir, 
@@ -33,12 +33,27 @@ fn is_book(entry: &DirEntry) -> Option<BookType> {
 }
 
 
+///Checks that a symlinked entry resolves to somewhere inside the source root.
+///Returns the resolved path as the error so it can be reported.
+fn check_symlink_target(entry: &DirEntry, canonical_root: &Path) -> Result<(), PathBuf> {
+    match entry.path().canonicalize() {
+        Ok(target) if target.starts_with(canonical_root) => Ok(()),
+        Ok(target) => Err(target),
+        //broken links resolve to nothing, so report the link itself
+        Err(_) => Err(entry.path().to_path_buf()),
+    }
+}
+
+
 ///Walks a given path looking for either PDFs or book.tomel files
-pub fn index_books(path: &PathBuf) -> Vec<(BookType, PathBuf)> {
+pub fn index_books(path: &PathBuf, follow_symlinks: bool) -> Vec<(BookType, PathBuf)> {
     let mut search_results = vec!();
 
+    //symlink targets are compared against the resolved root so links back into the tree are allowed
+    let canonical_root = path.canonicalize().unwrap_or_else(|_| path.clone());
+
     //build the iterator so we can start the dir walk
-    let mut it = WalkDir::new(path).into_iter();
+    let mut it = WalkDir::new(path).follow_links(follow_symlinks).into_iter();
     
     //we need to control the iterator a little more closely then a simple for loop allows
     loop {
@@ -47,9 +62,36 @@ pub fn index_books(path: &PathBuf) -> Vec<(BookType, PathBuf)> {
         let entry = match it.next() {
             Some(Ok(entry)) => entry,
             None => break, //if there is no next we end the loop
+            
+            //walkdir detects loops for us when following links, we just need to report them
+            Some(Err(err)) if err.loop_ancestor().is_some() => {
+                log::warn!("Refusing to follow symlink {} as it loops back to {}.",
+                           err.path().unwrap_or(path).display(),
+                           err.loop_ancestor().unwrap().display());
+                continue;
+            },
             _ => continue, //we just skip error files and 
         };
         
+        //books are copied and loaded through their links, so without following none are used at all
+        if entry.path_is_symlink() && ! follow_symlinks {
+            log::debug!("Skipping symlink {} as follow-symlinks is off.", entry.path().display());
+            continue;
+        }
+        
+        //never publish anything a link points to outside of the source directory
+        if entry.path_is_symlink() {
+            if let Err(target) = check_symlink_target(&entry, &canonical_root) {
+                log::warn!("Refusing to follow symlink {} as it resolves to {} which is outside of the source directory {}.",
+                           entry.path().display(), target.display(), canonical_root.display());
+                
+                if entry.file_type().is_dir() {
+                    it.skip_current_dir();
+                }
+                continue;
+            }
+        }
+        
         
         //test if entry denotes a valid book we can recognize
         let book_type = match is_book(&entry) {
@@ -85,3 +127,65 @@ pub fn index_books(path: &PathBuf) -> Vec<(BookType, PathBuf)> {
 }
 
 
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::{fs, os::unix::fs::symlink};
+
+
+    ///A bookshelf with a PDF, an mdBook, and a directory outside of it holding a secret.
+    fn bookshelf() -> (tempfile::TempDir, PathBuf) {
+        let project = tempfile::tempdir().unwrap();
+        let shelf = project.path().join("bookshelf");
+
+        fs::create_dir_all(shelf.join("Shelf/guide")).unwrap();
+        fs::write(shelf.join("Shelf/spec.pdf"), "").unwrap();
+        fs::write(shelf.join("Shelf/guide/book.toml"), "").unwrap();
+        fs::create_dir(project.path().join("outside")).unwrap();
+        fs::write(project.path().join("outside/secret.pdf"), "").unwrap();
+
+        (project, shelf)
+    }
+
+
+    fn found(shelf: &PathBuf, follow_symlinks: bool) -> Vec<PathBuf> {
+        let mut books: Vec<PathBuf> = index_books(shelf, follow_symlinks).into_iter()
+            .map(|(_, path)| path.strip_prefix(shelf).unwrap().to_path_buf())
+            .collect();
+        books.sort();
+        books
+    }
+
+
+    #[test]
+    fn symlinks_are_left_out_when_not_following() {
+        let (project, shelf) = bookshelf();
+        symlink(project.path().join("outside/secret.pdf"), shelf.join("leak.pdf")).unwrap();
+        symlink(shelf.join("Shelf/spec.pdf"), shelf.join("inside.pdf")).unwrap();
+        symlink(shelf.join("Shelf"), shelf.join("Linked")).unwrap();
+
+        assert_eq!(found(&shelf, false), [PathBuf::from("Shelf/guide"), PathBuf::from("Shelf/spec.pdf")]);
+    }
+
+
+    #[test]
+    fn followed_symlinks_stay_inside_the_bookshelf() {
+        let (project, shelf) = bookshelf();
+        symlink(project.path().join("outside/secret.pdf"), shelf.join("leak.pdf")).unwrap();
+        symlink(project.path().join("outside"), shelf.join("Outside")).unwrap();
+        symlink(shelf.join("Shelf/spec.pdf"), shelf.join("inside.pdf")).unwrap();
+        symlink(shelf.join("missing.pdf"), shelf.join("broken.pdf")).unwrap();
+
+        assert_eq!(found(&shelf, true), [PathBuf::from("Shelf/guide"), PathBuf::from("Shelf/spec.pdf"), PathBuf::from("inside.pdf")]);
+    }
+
+
+    #[test]
+    fn followed_symlinks_never_loop() {
+        let (_project, shelf) = bookshelf();
+        symlink(shelf.join("Shelf"), shelf.join("Shelf/again")).unwrap();
+
+        assert_eq!(found(&shelf, true), [PathBuf::from("Shelf/guide"), PathBuf::from("Shelf/spec.pdf")]);
+    }
+}
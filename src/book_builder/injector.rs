@@ -0,0 +1,196 @@
+use std::{
+    path::{Path, PathBuf},
+    fs,
+    rc::Rc,
+};
+
+use anyhow::Context;
+use handlebars::Handlebars;
+use serde_json::json;
+use walkdir::WalkDir;
+
+use mdbook::MDBook;
+
+
+use crate::config::ColorTheme;
+use crate::book::{Breadcrumb, SeriesPlacement, VersionPlacement, TranslationPlacement};
+use crate::page_builder::catalog::Catalog;
+use crate::page_builder::helpers::register_helpers;
+
+use super::overrides::InlinedFiles;
+
+
+///mdBook's variable leading from a page back to the book's html directory, left in the navigation
+/// for mdBook to fill in when links are relative.
+pub const PATH_TO_ROOT: &str = "{{path_to_root}}";
+
+
+///Everything the bookshelf adds to an mdBook's pages.
+pub struct BookInjection {
+    pub site_title: String,
+    pub index_url: String,
+    ///prefix of every link leading to the site root, which relative links climb to from each page
+    /// with mdBook's own `path_to_root`
+    pub site_root: String,
+    pub relative_links: bool,
+    pub shelves: Vec<Breadcrumb>,
+    pub series: Option<SeriesPlacement>,
+    pub version: Option<VersionPlacement>,
+    pub translation: Option<TranslationPlacement>,
+    ///colour theme of the bookshelf, until the visitor picks their own
+    pub color_theme: ColorTheme,
+    
+    ///strings of the navigation in the bookshelf's language
+    pub catalog: Rc<Catalog>,
+    ///template of the navigation, from the theme
+    pub navigation: Rc<String>,
+    
+    ///shelf wide stylesheets and scripts placed into every page's head
+    pub inlined: InlinedFiles,
+}
+
+
+///Recursively copies a directory, creating the destination as needed.
+pub fn copy_dir_all(from: &Path, to: &Path) -> Result<(), anyhow::Error> {
+    for entry in WalkDir::new(from) {
+        let entry = entry?;
+        let target = to.join(entry.path().strip_prefix(from)?);
+
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)
+                .with_context(|| format!("Unable to create {}", target.display()))?;
+        } else {
+            fs::copy(entry.path(), &target)
+                .with_context(|| format!("Unable to copy {} to {}", entry.path().display(), target.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+
+///Reads each file and wraps it in the given tag so it can be added to the head partial.
+///mdBook renders the head partial with handlebars so any braces are escaped.
+fn inline_files(files: &[PathBuf], tag: &str) -> Result<String, anyhow::Error> {
+    let mut inlined = String::new();
+    
+    for file in files {
+        let contents = fs::read_to_string(file)
+            .with_context(|| format!("Unable to read shelf file {}", file.display()))?;
+        
+        inlined.push_str(&format!("<{tag}>\n{}\n</{tag}>\n", contents.replace("{{", "\\{{"), tag = tag));
+    }
+    
+    Ok(inlined)
+}
+
+
+///Renders the shelf navigation bar shown at the top of every page of a book.
+///mdBook renders the header partial with handlebars again, so braces from names and titles are
+/// escaped and only its own `path_to_root` is left for it.
+fn render_navigation(injection: &BookInjection, book_title: &str) -> Result<String, anyhow::Error> {
+    let mut handlebars = Handlebars::new();
+    register_helpers(&mut handlebars);
+    injection.catalog.register(&mut handlebars);
+    handlebars.register_template_string("book_nav", injection.navigation.as_str())?;
+
+    let data = json!({
+        "site_title": injection.site_title,
+        "index_url": injection.index_url,
+        "site_root": injection.site_root,
+        "relative_links": injection.relative_links,
+        "shelves": injection.shelves,
+        "series": injection.series,
+        "version": injection.version,
+        "translation": injection.translation,
+        "color_theme": injection.color_theme,
+        "book_title": book_title,
+    });
+
+    let navigation = handlebars.render("book_nav", &data)?;
+
+    Ok(navigation.replace("{{", "\\{{").replace(&format!("\\{}", PATH_TO_ROOT), PATH_TO_ROOT))
+}
+
+
+///Builds a theme override for the book in the staging directory and points the book at it.
+///The book's own theme directory is copied first so any customisations it has are kept, then
+/// the bookshelf navigation is prepended to its header partial. The book source is never modified.
+pub fn inject_theme(md: &mut MDBook, injection: &BookInjection, staging_dir: &Path) -> Result<(), anyhow::Error> {
+    //only the html renderer uses a theme. mdBook defaults to html when no output is configured.
+    if md.config.get("output").is_some() && md.config.get("output.html").is_none() {
+        log::debug!("> Book has no html output, skipping theme injection.");
+        return Ok(());
+    }
+
+    let book_theme_dir: PathBuf = match md.config.html_config() {
+        Some(html_config) => html_config.theme_dir(&md.root),
+        None => md.root.join("theme"),
+    };
+
+    if staging_dir.exists() {
+        fs::remove_dir_all(staging_dir)
+            .with_context(|| format!("Unable to clear {}", staging_dir.display()))?;
+    }
+    fs::create_dir_all(staging_dir)
+        .with_context(|| format!("Unable to create {}", staging_dir.display()))?;
+
+    if book_theme_dir.is_dir() {
+        copy_dir_all(&book_theme_dir, staging_dir)?;
+    }
+
+    //keep whatever header the book already had underneath the navigation bar
+    let header_path = staging_dir.join("header.hbs");
+    let book_header = fs::read_to_string(&header_path).unwrap_or_default();
+
+    let book_title = md.config.book.title.clone().unwrap_or_default();
+    let navigation = render_navigation(injection, &book_title)?;
+
+    fs::write(&header_path, format!("{}\n{}", navigation, book_header))
+        .with_context(|| format!("Unable to write {}", header_path.display()))?;
+
+    //shelf wide stylesheets and scripts go after anything the book adds to its head
+    if ! injection.inlined.stylesheets.is_empty() || ! injection.inlined.scripts.is_empty() {
+        let head_path = staging_dir.join("head.hbs");
+        let book_head = fs::read_to_string(&head_path).unwrap_or_default();
+        
+        let head = format!("{}\n{}{}", book_head,
+                           inline_files(&injection.inlined.stylesheets, "style")?,
+                           inline_files(&injection.inlined.scripts, "script")?);
+        
+        fs::write(&head_path, head)
+            .with_context(|| format!("Unable to write {}", head_path.display()))?;
+    }
+
+    md.config.set("output.html.theme", staging_dir)?;
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn navigation_braces_are_escaped_for_mdbook() {
+        let injection = BookInjection {
+            site_title: "{{title}} Library".to_string(),
+            index_url: String::new(),
+            site_root: format!("{}../", PATH_TO_ROOT),
+            relative_links: true,
+            shelves: vec!(),
+            series: None,
+            version: None,
+            translation: None,
+            color_theme: ColorTheme::default(),
+            catalog: Rc::new(Catalog::default()),
+            navigation: Rc::new("<a href=\"{{site_root}}\">{{site_title}}</a> {{book_title}}".to_string()),
+            inlined: InlinedFiles::default(),
+        };
+
+        assert_eq!(render_navigation(&injection, "Using {{#each}}").unwrap(),
+                   "<a href=\"{{path_to_root}}../\">\\{{title}} Library</a> Using \\{{#each}}");
+    }
+}
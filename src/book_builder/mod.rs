@@ -5,20 +5,58 @@ mod builder;
 pub use builder::build_books;
 
 mod hierarchy;
-pub use hierarchy::compile_hierarchy;
+pub use hierarchy::{compile_hierarchy, compile_tag_shelves};
+
+mod injector;
+
+mod overrides;
+
+mod preprocessors;
+
+mod sandbox;
+
+mod shelf_links;
+
+mod common;
+
+mod series;
+
+mod versions;
+
+mod translations;
+use translations::shelf_languages;
+
+mod pdf;
+pub use pdf::page_texts;
+
+mod slugs;
+use slugs::Slugs;
 
 
 use std::path::PathBuf;
 
-use crate::book::BookshelfMetadata;
+use crate::book::{BookshelfMetadata, BookType};
+use crate::config::Config;
+use crate::page_builder::catalog::Catalog;
+use crate::page_builder::theme::Theme;
 
-pub fn build_bookshelf(src: PathBuf, bld: PathBuf, bookshelf_directory: PathBuf) -> BookshelfMetadata {
+pub fn build_bookshelf(config: &Config, theme: &Theme, catalog: &Catalog, src: PathBuf, bld: PathBuf, bookshelf_directory: PathBuf) -> BookshelfMetadata {
+    
+    let books_index = index_books(&src, config.bookshelf.follow_symlinks);
+    
+    //every url is known up front so clashing slugs are settled the same way on every build
+    let slugs = Slugs::new(config.bookshelf.slug_urls, 
+                           books_index.iter().filter_map(|(book_type, path)| Some( (path.strip_prefix(&src).ok()?, *book_type == BookType::PDF) )));
+    
+    let books_metadata = build_books( config, theme, catalog, books_index, &slugs, src.clone(), bld.clone(), bookshelf_directory.clone() );
+    let languages = shelf_languages(&books_metadata);
     
-    let books_index = index_books(&src);
+    let mut book_hierarchy = compile_hierarchy(books_metadata, &src, config.bookshelf.sort, &slugs);
     
-    let books_metadata = build_books( books_index, src.clone(), bld.clone(), bookshelf_directory.clone() );
+    //books directly in the bookshelf directory sit on the root section, which is shown as its own shelf
+    book_hierarchy.name = config.bookshelf.root_shelf_name.clone();
     
-    let book_hierarchy = compile_hierarchy(books_metadata);
+    let tag_shelves = compile_tag_shelves(&book_hierarchy, config.bookshelf.sort, &slugs);
     
     BookshelfMetadata {
         source_directory: src,
@@ -26,5 +64,7 @@ pub fn build_bookshelf(src: PathBuf, bld: PathBuf, bookshelf_directory: PathBuf)
         bookshelf_directory,
         
         book_hierarchy,
+        tag_shelves,
+        languages,
     }
 }
@@ -0,0 +1,163 @@
+use std::path::{Path, PathBuf};
+
+use toml::{Value, value::Table};
+
+use mdbook::MDBook;
+
+use crate::config::ColorTheme;
+
+
+///Placeholder replaced with the book's path inside the bookshelf in any shelf wide string setting.
+///Handy for things like `git-repository-url` where every book lives in a different sub-directory.
+pub const BOOK_PATH_PLACEHOLDER: &str = "{book-path}";
+
+///Shelf wide keys holding paths. These are written relative to the project root so they need
+/// to be made absolute before mdBook resolves them against each book's root.
+const PATH_KEYS: [&str; 1] = ["output.html.theme"];
+
+///Shelf wide keys holding lists of files that mdBook copies relative to the book root.
+///These can't point outside of the book so the files are inlined into the injected theme instead.
+const INLINED_KEYS: [&str; 2] = ["output.html.additional-css", "output.html.additional-js"];
+
+///Keys picking the theme a book is shown in before the visitor chooses one.
+const COLOR_THEME_KEYS: [&str; 2] = ["output.html.default-theme", "output.html.preferred-dark-theme"];
+
+///Key holding the url a book is hosted at.
+const SITE_URL_KEY: &str = "output.html.site-url";
+
+
+///Stylesheets and scripts from the shelf config that need to be inlined into a book's theme.
+#[derive(Debug, Default)]
+pub struct InlinedFiles {
+    pub stylesheets: Vec<PathBuf>,
+    pub scripts: Vec<PathBuf>,
+}
+
+
+///Flattens a nested table into a list of dotted keys and their leaf values.
+fn flatten(prefix: &str, table: &Table, leaves: &mut Vec<(String, Value)>) {
+    for (key, value) in table {
+        let full_key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+
+        match value {
+            Value::Table(sub_table) => flatten(&full_key, sub_table, leaves),
+            _ => leaves.push( (full_key, value.clone()) ),
+        }
+    }
+}
+
+
+///Replaces the book path placeholder in every string found in the value.
+fn substitute(value: Value, book_path: &str) -> Value {
+    match value {
+        Value::String(string) => Value::String(string.replace(BOOK_PATH_PLACEHOLDER, book_path)),
+        Value::Array(array) => Value::Array(array.into_iter().map(|item| substitute(item, book_path)).collect()),
+        other => other,
+    }
+}
+
+
+///Collects the string entries of a list value as paths relative to the project root.
+fn project_paths(value: &Value, project_root: &Path) -> Vec<PathBuf> {
+    value.as_array()
+         .map(|array| array.iter().filter_map(Value::as_str).map(|path| project_root.join(path)).collect())
+         .unwrap_or_default()
+}
+
+
+///Whether the book accepts the shelf wide settings. Books opt out with `[bookshelf] inherit = false`.
+pub fn inherits_shelf_config(md: &MDBook) -> bool {
+    md.config.get("bookshelf.inherit").and_then(Value::as_bool) != Some(false)
+}
+
+
+///Whether the book asked to keep its own value for the key through `[bookshelf] preserve`.
+///Preserving a table keeps everything under it too.
+pub fn is_preserved(md: &MDBook, key: &str) -> bool {
+    md.config.get("bookshelf.preserve")
+        .and_then(Value::as_array)
+        .map(|keys| keys.iter()
+                        .filter_map(Value::as_str)
+                        .any(|keep| key == keep || key.starts_with(&format!("{}.", keep))))
+        .unwrap_or(false)
+}
+
+
+///Merges the shelf wide `[mdbook]` settings over a book's own configuration.
+///Shelf settings win unless the book opts out with `[bookshelf] inherit = false`, or lists
+/// the keys it wants to keep in `[bookshelf] preserve`.
+pub fn apply_shelf_config(md: &mut MDBook, shelf_config: &Table, project_root: &Path, partial_path: &Path) -> InlinedFiles {
+    let mut inlined = InlinedFiles::default();
+
+    if ! inherits_shelf_config(md) {
+        log::info!("> Book opted out of the shelf wide mdBook settings.");
+        return inlined;
+    }
+
+    let book_path = partial_path.to_string_lossy();
+    let book_path = book_path.trim_end_matches('/');
+
+    let mut leaves = vec!();
+    flatten("", shelf_config, &mut leaves);
+
+    for (key, value) in leaves {
+        if is_preserved(md, &key) {
+            log::debug!("> Keeping book setting {}", key);
+            continue;
+        }
+
+        let value = substitute(value, book_path);
+
+        if key == INLINED_KEYS[0] {
+            inlined.stylesheets = project_paths(&value, project_root);
+            continue;
+        } else if key == INLINED_KEYS[1] {
+            inlined.scripts = project_paths(&value, project_root);
+            continue;
+        }
+
+        let value = match value.as_str() {
+            Some(path) if PATH_KEYS.contains(&key.as_str()) => Value::String(project_root.join(path).to_string_lossy().to_string()),
+            _ => value,
+        };
+
+        if let Err(err) = md.config.set(&key, value) {
+            log::error!("Unable to apply shelf setting {}: {}", key, err);
+        }
+    }
+
+    inlined
+}
+
+
+///Sets a value the bookshelf works out for the book, like any shelf setting. The same key set under
+/// `[mdbook]` in the shelf config wins, and books keep their own when they opt out or preserve the key.
+fn apply_bookshelf_value(md: &mut MDBook, key: &str, value: &str, shelf_config: &Table) {
+    let mut shelf_keys = vec!();
+    flatten("", shelf_config, &mut shelf_keys);
+
+    if ! inherits_shelf_config(md) || is_preserved(md, key) || shelf_keys.iter().any(|(shelf_key, _)| shelf_key == key) {
+        return;
+    }
+
+    if let Err(err) = md.config.set(key, value) {
+        log::error!("Unable to apply bookshelf setting {}: {}", key, err);
+    }
+}
+
+
+///Shows the book in the mdBook theme closest to the bookshelf's colour theme, so the whole site matches.
+pub fn apply_color_theme(md: &mut MDBook, color_theme: ColorTheme, shelf_config: &Table) {
+    if let Some(mdbook_theme) = color_theme.mdbook_theme() {
+        for key in COLOR_THEME_KEYS {
+            apply_bookshelf_value(md, key, mdbook_theme, shelf_config);
+        }
+    }
+}
+
+
+///Gives the book the url its html output is hosted at, which mdBook needs for its 404 page to
+/// find the book's stylesheets and chapters.
+pub fn apply_site_url(md: &mut MDBook, site_url: &str, shelf_config: &Table) {
+    apply_bookshelf_value(md, SITE_URL_KEY, site_url, shelf_config);
+}
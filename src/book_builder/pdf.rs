@@ -0,0 +1,41 @@
+use std::{
+    panic,
+    path::Path,
+};
+
+use lopdf::Document;
+
+
+///Number of pages in a PDF, or None if the file can't be read as one.
+pub fn page_count(path: &Path) -> Option<usize> {
+    match Document::load(path) {
+        Ok(document) => Some(document.get_pages().len()),
+        Err(err) => {
+            log::warn!("Unable to read the pages of {}: {}", path.display(), err);
+            None
+        },
+    }
+}
+
+
+///Text of every page of a PDF, first page first, with runs of whitespace collapsed.
+///Scanned PDFs only have text when they were run through OCR, otherwise their pages come back empty.
+pub fn page_texts(path: &Path) -> Option<Vec<String>> {
+    //the extractor panics on some malformed fonts rather than returning an error
+    let extracted = panic::catch_unwind(|| pdf_extract::extract_text_by_pages(path));
+
+    match extracted {
+        Ok(Ok(pages)) => Some(pages.iter().map(|page| page.split_whitespace().collect::<Vec<&str>>().join(" ")).collect()),
+        Ok(Err(err)) => {
+            log::warn!("Unable to extract the text of {}: {}", path.display(), err);
+            None
+        },
+        Err(payload) => {
+            let reason = payload.downcast_ref::<&str>().map(ToString::to_string)
+                                .or_else(|| payload.downcast_ref::<String>().cloned())
+                                .unwrap_or_default();
+            log::warn!("Unable to extract the text of {}, it isn't searchable: {}", path.display(), reason);
+            None
+        },
+    }
+}
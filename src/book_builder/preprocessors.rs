@@ -0,0 +1,70 @@
+use toml::{Value, value::Table};
+
+use mdbook::{
+    MDBook,
+    preprocess::{CmdPreprocessor, IndexPreprocessor, LinkPreprocessor},
+};
+
+use super::overrides::{inherits_shelf_config, is_preserved};
+
+
+///Names of mdBook's built-in preprocessors.
+const LINKS: &str = "links";
+const INDEX: &str = "index";
+
+///Preprocessors mdBook runs by default unless `build.use-default-preprocessors` is turned off.
+const DEFAULT_PREPROCESSORS: [&str; 2] = [LINKS, INDEX];
+
+
+///Names of the preprocessors mdBook set up for the book. Like renderers these are decided when
+/// the book is loaded, so this must be called before the shelf settings are merged in.
+pub fn loaded_preprocessors(md: &MDBook) -> Vec<String> {
+    let mut names: Vec<String> = vec!();
+    
+    if md.config.build.use_default_preprocessors {
+        names.extend(DEFAULT_PREPROCESSORS.iter().map(|name| name.to_string()));
+    }
+    
+    if let Some(table) = md.config.get("preprocessor").and_then(Value::as_table) {
+        names.extend(table.keys().cloned());
+    }
+    
+    names
+}
+
+
+///Registers every preprocessor declared in the shelf wide `[mdbook.preprocessor]` table with the
+/// book. The built-in ones are added directly, anything else is run as a command the same way
+/// mdBook would, defaulting to `mdbook-<name>`. Preprocessors the book already runs are skipped.
+pub fn register_shelf_preprocessors(md: &mut MDBook, shelf_config: &Table, loaded: &[String]) {
+    let shelf_preprocessors = match shelf_config.get("preprocessor").and_then(Value::as_table) {
+        Some(table) => table,
+        None => return,
+    };
+    
+    if ! inherits_shelf_config(md) {
+        return;
+    }
+    
+    for (name, table) in shelf_preprocessors {
+        if loaded.contains(name) || is_preserved(md, &format!("preprocessor.{}", name)) {
+            log::debug!("> Book already configures preprocessor {}, not adding the shelf one.", name);
+            continue;
+        }
+        
+        log::debug!("> Adding shelf preprocessor {}", name);
+        
+        match name.as_str() {
+            LINKS => { md.with_preprocessor(LinkPreprocessor::new()); },
+            INDEX => { md.with_preprocessor(IndexPreprocessor::new()); },
+            _ => {
+                let command = table.get("command")
+                                   .and_then(Value::as_str)
+                                   .map(ToString::to_string)
+                                   .unwrap_or_else(|| format!("mdbook-{}", name));
+                
+                md.with_preprocessor(CmdPreprocessor::new(name.clone(), command));
+            },
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+
+use mdbook::Config as BookConfig;
+
+use crate::config::{SandboxConfig, SandboxMode};
+
+
+///Renderers implemented inside mdBook. Any other renderer runs an external command.
+const BUILTIN_RENDERERS: [&str; 2] = ["html", "markdown"];
+
+///Preprocessors implemented inside mdBook. Any other preprocessor runs an external command.
+const BUILTIN_PREPROCESSORS: [&str; 2] = ["links", "index"];
+
+
+///An external command a book's configuration asks mdBook to run.
+#[derive(Debug, Clone)]
+pub struct BookCommand {
+    pub key: String, //config table declaring the command, ie "preprocessor.mermaid"
+    pub command: String,
+}
+
+
+///Books the sandbox refused to build or built without some of their commands.
+#[derive(Debug, Default)]
+pub struct SandboxReport {
+    pub blocked: Vec<(PathBuf, Vec<BookCommand>)>,
+    pub downgraded: Vec<(PathBuf, Vec<BookCommand>)>,
+}
+
+
+impl SandboxReport {
+    ///Writes the report to the log once all books are built.
+    pub fn log(&self) {
+        if self.blocked.is_empty() && self.downgraded.is_empty() {
+            return;
+        }
+
+        log::warn!("Sandbox report: {} book(s) blocked, {} book(s) built without disallowed commands.",
+                   self.blocked.len(), self.downgraded.len());
+
+        for (book, commands) in &self.blocked {
+            log::warn!("> Blocked \"{}\"", book.display());
+            for command in commands {
+                log::warn!(">   {} = \"{}\"", command.key, command.command);
+            }
+        }
+
+        for (book, commands) in &self.downgraded {
+            log::warn!("> Downgraded \"{}\"", book.display());
+            for command in commands {
+                log::warn!(">   removed {} = \"{}\"", command.key, command.command);
+            }
+        }
+    }
+}
+
+
+///Collects every external command declared by the book's preprocessors and renderers.
+///mdBook defaults to running `mdbook-<name>` when no command is given.
+fn book_commands(book_config: &BookConfig) -> Vec<BookCommand> {
+    let mut commands = vec!();
+
+    for (section, builtins) in [("preprocessor", BUILTIN_PREPROCESSORS), ("output", BUILTIN_RENDERERS)] {
+        let table = match book_config.get(section).and_then(Value::as_table) {
+            Some(table) => table,
+            None => continue,
+        };
+
+        for (name, settings) in table.iter().filter(|(name, _)| ! builtins.contains(&name.as_str())) {
+            let command = settings.get("command")
+                                  .and_then(Value::as_str)
+                                  .map(ToString::to_string)
+                                  .unwrap_or_else(|| format!("mdbook-{}", name));
+
+            commands.push( BookCommand { key: format!("{}.{}", section, name), command } );
+        }
+    }
+
+    commands
+}
+
+
+///Checks a command against the policy. Allowlist entries match either the whole command or
+/// just the program it runs.
+fn is_allowed(policy: &SandboxConfig, command: &str) -> bool {
+    match policy.mode {
+        SandboxMode::Trusted => true,
+        SandboxMode::NoCommands => false,
+        SandboxMode::Allowlist => {
+            let program = command.split_whitespace().next().unwrap_or_default();
+
+            policy.allowed_commands.iter().any(|allowed| allowed == command || allowed == program)
+        },
+    }
+}
+
+
+///Reads a book's configuration the same way `MDBook::load` does, so it can be checked before
+/// mdBook sets up any commands. Returns the config and the commands the policy disallows.
+pub fn load_book_config(policy: &SandboxConfig, book_root: &Path) -> Result<(BookConfig, Vec<BookCommand>), anyhow::Error> {
+    let config_location = book_root.join("book.toml");
+
+    let mut book_config = if config_location.exists() {
+        BookConfig::from_disk(&config_location)?
+    } else {
+        BookConfig::default()
+    };
+
+    //environment overrides can declare commands too, so check after applying them
+    book_config.update_from_env();
+
+    let disallowed = book_commands(&book_config).into_iter()
+                                                 .filter(|command| ! is_allowed(policy, &command.command))
+                                                 .collect();
+
+    Ok( (book_config, disallowed) )
+}
+
+
+///Drops the preprocessor and renderer tables that declared the given commands.
+pub fn strip_commands(book_config: &mut BookConfig, commands: &[BookCommand]) {
+    for command in commands {
+        if let Some((section, name)) = command.key.split_once('.') {
+            if let Some(table) = book_config.get_mut(section).and_then(Value::as_table_mut) {
+                table.remove(name);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::SandboxViolation;
+
+
+    fn policy(mode: SandboxMode, allowed_commands: &[&str]) -> SandboxConfig {
+        SandboxConfig {
+            mode,
+            allowed_commands: allowed_commands.iter().map(ToString::to_string).collect(),
+            on_violation: SandboxViolation::default(),
+        }
+    }
+
+
+    fn book_config(toml: &str) -> BookConfig {
+        toml.parse().expect("test book.toml is valid")
+    }
+
+
+    #[test]
+    fn modes_allow_their_commands() {
+        assert!(is_allowed(&policy(SandboxMode::Trusted, &[]), "anything --at all"));
+        assert!(! is_allowed(&policy(SandboxMode::NoCommands, &["mdbook-mermaid"]), "mdbook-mermaid"));
+    }
+
+
+    #[test]
+    fn allowlist_matches_commands_or_programs() {
+        let policy = policy(SandboxMode::Allowlist, &["mdbook-mermaid", "python3 tools/toc.py"]);
+
+        assert!(is_allowed(&policy, "mdbook-mermaid"));
+        assert!(is_allowed(&policy, "mdbook-mermaid --strict"));
+        assert!(is_allowed(&policy, "python3 tools/toc.py"));
+
+        assert!(! is_allowed(&policy, "python3 tools/other.py"));
+        assert!(! is_allowed(&policy, "python3"));
+        assert!(! is_allowed(&policy, "mdbook-mermaid2"));
+        assert!(! is_allowed(&policy, "/usr/bin/mdbook-mermaid"));
+        assert!(! is_allowed(&policy, ""));
+    }
+
+
+    #[test]
+    fn commands_default_to_mdbook_programs_and_skip_builtins() {
+        let config = book_config(r#"
+            [preprocessor.links]
+            [preprocessor.index]
+            [preprocessor.mermaid]
+            [preprocessor.toc]
+            command = "python3 toc.py"
+            [output.html]
+            [output.markdown]
+            [output.pdf]
+        "#);
+
+        let mut commands: Vec<(String, String)> = book_commands(&config).into_iter().map(|command| (command.key, command.command)).collect();
+        commands.sort();
+
+        assert_eq!(commands, [
+            ("output.pdf".to_string(), "mdbook-pdf".to_string()),
+            ("preprocessor.mermaid".to_string(), "mdbook-mermaid".to_string()),
+            ("preprocessor.toc".to_string(), "python3 toc.py".to_string()),
+        ]);
+    }
+
+
+    #[test]
+    fn stripping_removes_only_disallowed_tables() {
+        let mut config = book_config(r#"
+            [preprocessor.mermaid]
+            [preprocessor.toc]
+            command = "python3 toc.py"
+            [output.html]
+            [output.pdf]
+        "#);
+
+        let policy = policy(SandboxMode::Allowlist, &["mdbook-mermaid"]);
+        let disallowed: Vec<BookCommand> = book_commands(&config).into_iter().filter(|command| ! is_allowed(&policy, &command.command)).collect();
+        strip_commands(&mut config, &disallowed);
+
+        assert!(config.get("preprocessor.mermaid").is_some());
+        assert!(config.get("preprocessor.toc").is_none());
+        assert!(config.get("output.html").is_some());
+        assert!(config.get("output.pdf").is_none());
+        assert!(book_commands(&config).iter().all(|command| is_allowed(&policy, &command.command)));
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::book::{Breadcrumb, SeriesPlacement};
+use crate::config::{ShelfConfig, SHELF_CONFIG_FILE};
+
+use super::hierarchy::natural_cmp;
+
+
+///A book that declared itself, or was declared by its shelf, part of a series.
+pub struct SeriesMember {
+    pub partial_path: PathBuf,
+    pub title: String,
+    pub url: String,
+
+    pub series: String,
+    pub volume: Option<i64>,
+}
+
+
+///Looks up the series a book's shelf.toml puts it in, along with its volume number counted from
+/// one in the order the shelf lists it. Shelf configs are cached as every book on a shelf asks.
+pub fn shelf_series(book_source_path: &Path, shelf_configs: &mut HashMap<PathBuf, ShelfConfig>) -> Option<(String, i64)> {
+    let shelf_dir = book_source_path.parent()?;
+    let book_name = book_source_path.file_name()?.to_string_lossy().to_string();
+
+    let shelf_config = shelf_configs.entry(shelf_dir.to_path_buf())
+                                    .or_insert_with(|| ShelfConfig::load(shelf_dir));
+
+    let listings: Vec<(String, i64)> = shelf_config.series.iter()
+        .filter_map(|(series, books)| {
+            let position = books.iter().position(|name| name.trim_end_matches('/') == book_name)?;
+            Some( (series.clone(), position as i64 + 1) )
+        })
+        .collect();
+
+    //a book can only be in one series, the first by name is kept so every build agrees
+    if listings.len() > 1 {
+        let names: Vec<&str> = listings.iter().map(|(series, _)| series.as_str()).collect();
+        log::error!("\"{}\" is listed in the series {} in {}, keeping it in \"{}\" only.",
+                    book_name, names.join(", "), shelf_dir.join(SHELF_CONFIG_FILE).display(), names[0]);
+    }
+
+    listings.into_iter().next()
+}
+
+
+///Orders the members of every series and works out each book's neighbours.
+///A series is made of the books on one shelf, as its card is, so shelves can each have a series
+/// of the same name. Volumes are read lowest first, with unnumbered books after them in natural name order.
+pub fn place_in_series(members: Vec<SeriesMember>) -> HashMap<PathBuf, SeriesPlacement> {
+    let mut series: HashMap<(PathBuf, String), Vec<SeriesMember>> = HashMap::new();
+    for member in members {
+        let shelf = member.partial_path.parent().map(Path::to_path_buf).unwrap_or_default();
+        series.entry( (shelf, member.series.clone()) ).or_default().push(member);
+    }
+
+    let mut placements = HashMap::new();
+
+    for ((_, name), mut volumes) in series {
+        volumes.sort_by(|a, b| {
+            let order = match (a.volume, b.volume) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            };
+
+            order.then_with(|| natural_cmp(&a.partial_path.to_string_lossy(), &b.partial_path.to_string_lossy()))
+        });
+
+        let link = |member: &SeriesMember| Breadcrumb { name: member.title.clone(), url: member.url.clone() };
+
+        for (index, member) in volumes.iter().enumerate() {
+            placements.insert(member.partial_path.clone(), SeriesPlacement {
+                name: name.clone(),
+                volume: index + 1,
+                volumes: volumes.len(),
+                previous: index.checked_sub(1).map(|previous| link(&volumes[previous])),
+                next: volumes.get(index + 1).map(link),
+            });
+        }
+    }
+
+    placements
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn member(partial_path: &str, series: &str, volume: Option<i64>) -> SeriesMember {
+        SeriesMember {
+            partial_path: PathBuf::from(partial_path),
+            title: partial_path.to_string(),
+            url: format!("bookshelf/{}", partial_path),
+            series: series.to_string(),
+            volume,
+        }
+    }
+
+
+    #[test]
+    fn volumes_are_read_in_order() {
+        let placements = place_in_series(vec!(
+            member("Training/extra.pdf", "Track", None),
+            member("Training/two.pdf", "Track", Some(2)),
+            member("Training/one.pdf", "Track", Some(1)),
+            member("Training/appendix.pdf", "Track", None),
+        ));
+
+        let first = &placements[Path::new("Training/one.pdf")];
+        assert_eq!((first.volume, first.volumes), (1, 4));
+        assert!(first.previous.is_none());
+        assert_eq!(first.next.as_ref().map(|next| next.name.as_str()), Some("Training/two.pdf"));
+
+        let unnumbered: Vec<usize> = ["Training/appendix.pdf", "Training/extra.pdf"].iter().map(|book| placements[Path::new(book)].volume).collect();
+        assert_eq!(unnumbered, [3, 4]);
+        assert!(placements[Path::new("Training/extra.pdf")].next.is_none());
+    }
+
+
+    #[test]
+    fn series_of_the_same_name_stay_on_their_shelves() {
+        let placements = place_in_series(vec!(
+            member("Alpha/a1.pdf", "Part", Some(1)),
+            member("Alpha/a2.pdf", "Part", Some(2)),
+            member("Beta/b1.pdf", "Part", Some(1)),
+        ));
+
+        let alpha = &placements[Path::new("Alpha/a2.pdf")];
+        assert_eq!((alpha.volume, alpha.volumes), (2, 2));
+        assert_eq!(alpha.previous.as_ref().map(|previous| previous.url.as_str()), Some("bookshelf/Alpha/a1.pdf"));
+
+        let beta = &placements[Path::new("Beta/b1.pdf")];
+        assert_eq!((beta.volume, beta.volumes), (1, 1));
+        assert!(beta.previous.is_none() && beta.next.is_none());
+    }
+
+
+    #[test]
+    fn shelf_series_are_read_from_shelf_toml() {
+        let shelf = tempfile::tempdir().unwrap();
+        std::fs::write(shelf.path().join(SHELF_CONFIG_FILE), "[series]\nZed = [\"one/\", \"two.pdf\"]\nAlpha = [\"two.pdf\"]\n").unwrap();
+
+        let mut shelf_configs = HashMap::new();
+
+        assert_eq!(shelf_series(&shelf.path().join("one"), &mut shelf_configs), Some( ("Zed".to_string(), 1) ));
+        //listed twice, the first series by name keeps it
+        assert_eq!(shelf_series(&shelf.path().join("two.pdf"), &mut shelf_configs), Some( ("Alpha".to_string(), 1) ));
+        assert_eq!(shelf_series(&shelf.path().join("three.pdf"), &mut shelf_configs), None);
+    }
+}
@@ -0,0 +1,360 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+};
+
+use mdbook::{
+    book::{Book, BookItem},
+    errors::{Error, Result},
+    preprocess::{Preprocessor, PreprocessorContext},
+};
+
+
+///Prefix marking a link target as a book on the shelf, ie `[see](shelf:networking/tcp-guide#chapter-3)`.
+pub const SHELF_LINK_SCHEME: &str = "shelf:";
+
+///Helper form that is replaced with the bare url, ie `{{#shelf-link networking/tcp-guide}}`.
+const SHELF_LINK_HELPER: &str = "{{#shelf-link ";
+
+
+///Maps every book's path inside the bookshelf, without a trailing slash, to the path of the page
+/// links to it should open, relative to the bookshelf build directory.
+pub type LinkTargets = HashMap<String, PathBuf>;
+
+
+///Shelf links that lead to no book on the shelf, gathered from every book and reported once all
+/// books are built. Each is kept as the book, chapter and target it was found with.
+#[derive(Debug, Default, Clone)]
+pub struct LinkReport {
+    unresolved: Rc<RefCell<BTreeSet<(String, String, String)>>>,
+}
+
+
+impl LinkReport {
+    ///Writes the report to the log once all books are built.
+    pub fn log(&self) {
+        let unresolved = self.unresolved.borrow();
+        if unresolved.is_empty() {
+            return;
+        }
+
+        log::error!("Shelf link report: {} link(s) lead to no book on the shelf.", unresolved.len());
+
+        let mut last_book = None;
+        for (book, chapter, target) in unresolved.iter() {
+            if last_book != Some(book) {
+                log::error!("> \"{}\"", book);
+                last_book = Some(book);
+            }
+            log::error!(">   \"{}\" in chapter \"{}\"", target, chapter);
+        }
+    }
+}
+
+
+///Finds where a `shelf:` link target starting at the given index ends. Markdown link
+/// destinations stop at the closing bracket or before an optional title, those in angle brackets
+/// can hold spaces and only stop at the closing one.
+fn link_end(line: &str, start: usize) -> usize {
+    let in_angle_brackets = line[..start].ends_with('<');
+
+    line[start..].find(|c: char| c == '>' || (! in_angle_brackets && (c == ')' || c.is_whitespace())))
+                 .map(|end| start + end)
+                 .unwrap_or(line.len())
+}
+
+
+///Page mdBook writes for a chapter file. The index preprocessor turns every `README.md` into
+/// `index.md`, like mdBook itself.
+fn chapter_page(chapter: &str) -> String {
+    let (directory, file) = match chapter.rsplit_once('/') {
+        Some((directory, file)) => (format!("{}/", directory), file),
+        None => (String::new(), chapter),
+    };
+
+    match file.strip_suffix(".md") {
+        Some(stem) if stem.eq_ignore_ascii_case("readme") => format!("{}index.html", directory),
+        Some(stem) => format!("{}{}.html", directory, stem),
+        None => chapter.to_string(),
+    }
+}
+
+
+///Preprocessor resolving `shelf:` links into relative urls to other books on the shelf.
+pub struct ShelfLinkPreprocessor {
+    targets: Rc<LinkTargets>,
+    report: LinkReport,
+    book_name: String,
+    ///whether links that lead nowhere fail the book's build
+    strict: bool,
+
+    ///where this book's html output sits inside the bookshelf build directory
+    html_path: PathBuf,
+}
+
+
+impl ShelfLinkPreprocessor {
+    pub const NAME: &'static str = "shelf-links";
+
+    pub fn new(targets: Rc<LinkTargets>, report: LinkReport, book_name: String, strict: bool, html_path: PathBuf) -> Self {
+        ShelfLinkPreprocessor { targets, report, book_name, strict, html_path }
+    }
+
+
+    ///Fails with every link of this book that leads nowhere when links are strict.
+    fn check_unresolved(&self) -> Result<()> {
+        if ! self.strict {
+            return Ok(());
+        }
+
+        let unresolved = self.report.unresolved.borrow();
+        let found: Vec<String> = unresolved.iter()
+            .filter(|(book, _, _)| *book == self.book_name)
+            .map(|(_, chapter, target)| format!("\"{}\" in chapter \"{}\"", target, chapter))
+            .collect();
+
+        if found.is_empty() {
+            return Ok(());
+        }
+
+        Err(Error::msg(format!("{} shelf link(s) in \"{}\" lead to no book on the shelf: {}. Set strict-shelf-links = false to build anyway.",
+                               found.len(), self.book_name, found.join(", "))))
+    }
+
+
+    ///Turns a shelf link target into a url relative to the chapter's page.
+    ///Returns None if no book on the shelf matches the target.
+    fn resolve(&self, target: &str, chapter_dir: &Path) -> Option<String> {
+        let (path, anchor) = match target.split_once('#') {
+            Some((path, anchor)) => (path, Some(anchor)),
+            None => (target, None),
+        };
+        let path = path.replace("%20", " ");
+        let path = path.trim_matches('/');
+
+        //the longest matching book path wins so nested books resolve correctly
+        let (book_path, page) = self.targets.iter()
+            .filter(|(book_path, _)| path == book_path.as_str() || path.starts_with(&format!("{}/", book_path)))
+            .max_by_key(|(book_path, _)| book_path.len())?;
+
+        let chapter = path[book_path.len()..].trim_start_matches('/');
+
+        let mut target_page = page.clone();
+        if ! chapter.is_empty() {
+            //only mdbooks have chapters, a pdf link path is the file itself
+            if ! page.to_string_lossy().ends_with('/') {
+                return None;
+            }
+            target_page.push(chapter_page(chapter));
+        }
+
+        //climb out of the chapter's directory back to the bookshelf build directory
+        let depth = self.html_path.join(chapter_dir)
+                                  .components()
+                                  .filter(|comp| matches!(comp, Component::Normal(_)))
+                                  .count();
+
+        let mut url = "../".repeat(depth);
+        url.push_str(&target_page.to_string_lossy().replace(' ', "%20"));
+
+        if let Some(anchor) = anchor {
+            url.push('#');
+            url.push_str(anchor);
+        }
+
+        Some(url)
+    }
+
+
+    ///Rewrites every shelf link in a single line, reporting any that can't be resolved.
+    fn rewrite_line(&self, line: &str, chapter_dir: &Path, chapter_name: &str) -> String {
+        let mut output = String::with_capacity(line.len());
+        let mut rest = line;
+
+        loop {
+            let helper = rest.find(SHELF_LINK_HELPER);
+            let scheme = rest.match_indices(SHELF_LINK_SCHEME)
+                             .map(|(index, _)| index)
+                             .find(|index| rest[..*index].ends_with('(') || rest[..*index].ends_with("]: ") || rest[..*index].ends_with('<'));
+
+            //handle whichever form comes first in the line
+            let (start, target_start, target_end, skip) = match (helper, scheme) {
+                (Some(h), Some(s)) if s < h => (s, s + SHELF_LINK_SCHEME.len(), link_end(rest, s), 0),
+                (Some(h), _) => match rest[h..].find("}}") {
+                    Some(end) => (h, h + SHELF_LINK_HELPER.len(), h + end, 2),
+                    None => break,
+                },
+                (None, Some(s)) => (s, s + SHELF_LINK_SCHEME.len(), link_end(rest, s), 0),
+                (None, None) => break,
+            };
+
+            let target = rest[target_start..target_end].trim();
+
+            output.push_str(&rest[..start]);
+            match self.resolve(target, chapter_dir) {
+                Some(url) => output.push_str(&url),
+                None => {
+                    //books with several renderers are preprocessed once for each, the report keeps one of each link
+                    self.report.unresolved.borrow_mut().insert( (self.book_name.clone(), chapter_name.to_string(), target.to_string()) );
+                    output.push_str(&rest[start..target_end + skip]);
+                },
+            }
+            rest = &rest[target_end + skip..];
+        }
+
+        output.push_str(rest);
+        output
+    }
+
+
+    ///Rewrites shelf links in a chapter's markdown, leaving fenced code blocks alone.
+    fn rewrite_content(&self, content: &str, chapter_dir: &Path, chapter_name: &str) -> String {
+        let mut in_code_block = false;
+
+        content.split_inclusive('\n')
+               .map(|line| {
+                   let trimmed = line.trim_start();
+                   if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                       in_code_block = ! in_code_block;
+                   }
+
+                   if in_code_block || ! (line.contains(SHELF_LINK_SCHEME) || line.contains(SHELF_LINK_HELPER)) {
+                       line.to_string()
+                   } else {
+                       self.rewrite_line(line, chapter_dir, chapter_name)
+                   }
+               })
+               .collect()
+    }
+}
+
+
+impl Preprocessor for ShelfLinkPreprocessor {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn run(&self, _ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
+        book.for_each_mut(|item| {
+            if let BookItem::Chapter(chapter) = item {
+                //draft chapters have no page so there is nothing to link from
+                let chapter_dir = match &chapter.path {
+                    Some(path) => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+                    None => return,
+                };
+
+                chapter.content = self.rewrite_content(&chapter.content, &chapter_dir, &chapter.name);
+            }
+        });
+
+        self.check_unresolved()?;
+
+        Ok(book)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn preprocessor(report: &LinkReport) -> ShelfLinkPreprocessor {
+        let targets: LinkTargets = [
+            ("networking/tcp-guide", "networking/tcp-guide/"),
+            ("networking/tcp-guide/appendix", "networking/tcp-guide/appendix/"),
+            ("specs/rfc 793.pdf", "specs/rfc 793.pdf"),
+        ].into_iter().map(|(book, page)| (book.to_string(), PathBuf::from(page))).collect();
+
+        ShelfLinkPreprocessor::new(Rc::new(targets), report.clone(), "guides/intro/".to_string(), false, PathBuf::from("guides/intro/"))
+    }
+
+
+    #[test]
+    fn resolves_books_chapters_and_anchors() {
+        let links = preprocessor(&LinkReport::default());
+        let root = Path::new("");
+
+        assert_eq!(links.resolve("networking/tcp-guide", root).as_deref(), Some("../../networking/tcp-guide/"));
+        assert_eq!(links.resolve("networking/tcp-guide/setup.md#ports", root).as_deref(), Some("../../networking/tcp-guide/setup.html#ports"));
+        assert_eq!(links.resolve("/specs/rfc%20793.pdf", Path::new("part")).as_deref(), Some("../../../specs/rfc%20793.pdf"));
+    }
+
+
+    #[test]
+    fn resolves_the_longest_matching_book() {
+        let links = preprocessor(&LinkReport::default());
+
+        assert_eq!(links.resolve("networking/tcp-guide/appendix/a.md", Path::new("")).as_deref(), Some("../../networking/tcp-guide/appendix/a.html"));
+    }
+
+
+    #[test]
+    fn readme_chapters_lead_to_index_pages() {
+        let links = preprocessor(&LinkReport::default());
+        let root = Path::new("");
+
+        assert_eq!(links.resolve("networking/tcp-guide/README.md", root).as_deref(), Some("../../networking/tcp-guide/index.html"));
+        assert_eq!(links.resolve("networking/tcp-guide/part/readme.md#top", root).as_deref(), Some("../../networking/tcp-guide/part/index.html#top"));
+        assert_eq!(links.resolve("networking/tcp-guide/readmes.md", root).as_deref(), Some("../../networking/tcp-guide/readmes.html"));
+    }
+
+
+    #[test]
+    fn angle_bracket_links_can_hold_spaces() {
+        let links = preprocessor(&LinkReport::default());
+
+        assert_eq!(links.rewrite_line("[spec](<shelf:specs/rfc 793.pdf> \"RFC\")", Path::new(""), "Intro"),
+                   "[spec](<../../specs/rfc%20793.pdf> \"RFC\")");
+        assert_eq!(links.rewrite_line("[spec](shelf:specs/rfc%20793.pdf \"RFC\")", Path::new(""), "Intro"),
+                   "[spec](../../specs/rfc%20793.pdf \"RFC\")");
+    }
+
+
+    #[test]
+    fn leaves_unresolved_links_and_reports_them_once() {
+        let report = LinkReport::default();
+        let links = preprocessor(&report);
+
+        assert_eq!(links.resolve("specs/rfc 793.pdf/page", Path::new("")), None);
+
+        let line = "See [nowhere](shelf:missing/book) and {{#shelf-link networking/tcp-guide}}.";
+        for _ in 0..2 {
+            assert_eq!(links.rewrite_line(line, Path::new(""), "Intro"), "See [nowhere](shelf:missing/book) and ../../networking/tcp-guide/.");
+        }
+
+        let unresolved = report.unresolved.borrow();
+        assert_eq!(unresolved.len(), 1);
+        assert!(unresolved.contains(&("guides/intro/".to_string(), "Intro".to_string(), "missing/book".to_string())));
+    }
+
+
+    #[test]
+    fn strict_links_fail_the_book() {
+        let report = LinkReport::default();
+        let mut links = preprocessor(&report);
+
+        links.rewrite_line("[nowhere](shelf:missing/book)", Path::new(""), "Intro");
+        assert!(links.check_unresolved().is_ok());
+
+        links.strict = true;
+        let err = links.check_unresolved().unwrap_err().to_string();
+        assert!(err.contains("\"missing/book\" in chapter \"Intro\""));
+
+        //links other books left unresolved are theirs to fail on
+        let other = ShelfLinkPreprocessor::new(Rc::new(LinkTargets::new()), report.clone(), "guides/other/".to_string(), true, PathBuf::new());
+        assert!(other.check_unresolved().is_ok());
+    }
+
+
+    #[test]
+    fn leaves_code_blocks_alone() {
+        let links = preprocessor(&LinkReport::default());
+        let content = "```\n[x](shelf:networking/tcp-guide)\n```\n[x](shelf:networking/tcp-guide)\n";
+
+        assert_eq!(links.rewrite_content(content, Path::new(""), "Intro"),
+                   "```\n[x](shelf:networking/tcp-guide)\n```\n[x](../../networking/tcp-guide/)\n");
+    }
+}
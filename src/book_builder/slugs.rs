@@ -0,0 +1,307 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+
+///Slug used when nothing of a name is left, ie for a shelf named `&`.
+const EMPTY_SLUG: &str = "untitled";
+
+///Characters a directory name can't keep when slugs are off, as they would nest the directory or
+/// cut its url short.
+const UNSAFE_CHARACTERS: [char; 5] = ['/', '\\', '#', '?', '%'];
+
+
+///Lowercases text and swaps everything but letters and digits for single dashes. Accents are
+/// dropped from latin letters, other letters are kept as they are. Dots are kept between digits so
+/// versions survive.
+fn slug_part(text: &str) -> String {
+    let chars: Vec<char> = text.nfc()
+        .flat_map(|c| {
+            //marks in other scripts make a different letter, ie the dakuten turning カ into ガ
+            let base: Vec<char> = std::iter::once(c).nfkd().filter(|c| ! is_combining_mark(*c)).collect();
+            if base.iter().all(char::is_ascii) { base } else { vec!(c) }
+        })
+        .flat_map(char::to_lowercase)
+        .collect();
+    let mut slug = String::new();
+
+    for (index, c) in chars.iter().enumerate() {
+        let between_digits = index > 0 && chars[index - 1].is_ascii_digit() && chars.get(index + 1).is_some_and(char::is_ascii_digit);
+
+        if c.is_alphanumeric() || (*c == '.' && between_digits) {
+            slug.push(*c);
+        } else if ! slug.is_empty() && ! slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.trim_end_matches('-').to_string()
+}
+
+
+///Turns a file or directory name into a url friendly one, ie `My Book (2nd ed).pdf` becomes
+/// `my-book-2nd-ed.pdf`. Files keep their extension.
+pub fn slugify(name: &str, is_file: bool) -> String {
+    let path = Path::new(name);
+
+    let (stem, extension) = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) if is_file => (slug_part(&stem.to_string_lossy()), Some(slug_part(&extension.to_string_lossy()))),
+        _ => (slug_part(name), None),
+    };
+
+    let stem = if stem.is_empty() { EMPTY_SLUG.to_string() } else { stem };
+
+    match extension {
+        Some(extension) if ! extension.is_empty() => format!("{}.{}", stem, extension),
+        _ => stem,
+    }
+}
+
+
+///Keeps a name as it is, but for the characters that can't be in a directory name that is also
+/// part of a url, which become dashes.
+fn safe_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if UNSAFE_CHARACTERS.contains(&c) || c.is_control() { '-' } else { c })
+        .collect()
+}
+
+
+///Claims a slug among its siblings. When another name already has it a number is added, and the
+/// clash is reported as visitors might expect either name's url to lead to the other.
+fn claim(taken: &mut HashMap<String, String>, slug: String, name: &str, is_file: bool) -> String {
+    let mut claimed = slug.clone();
+    let mut number = 2;
+
+    while let Some(owner) = taken.get(&claimed) {
+        if owner == name {
+            return claimed;
+        }
+
+        claimed = match Path::new(&slug).extension() {
+            Some(extension) if is_file => format!("{}-{}.{}", slug.trim_end_matches(&format!(".{}", extension.to_string_lossy())), number, extension.to_string_lossy()),
+            _ => format!("{}-{}", slug, number),
+        };
+        number += 1;
+    }
+
+    if claimed != slug {
+        log::error!("\"{}\" and \"{}\" would both be placed at \"{}\", using \"{}\" for \"{}\". Rename one of them to choose their urls.",
+                    taken[&slug], name, slug, claimed, name);
+    }
+
+    taken.insert(claimed.clone(), name.to_string());
+    claimed
+}
+
+
+///Where books and shelves are placed in the build directory, and so their urls. Without slugs these
+/// are their paths in the bookshelf directory, with slugs every name is made url friendly.
+///Books and shelves keep their original names everywhere they are shown.
+#[derive(Debug, Default)]
+pub struct Slugs {
+    enabled: bool,
+
+    ///slugged path of every book and shelf, keyed by its path in the bookshelf directory
+    paths: HashMap<PathBuf, PathBuf>,
+}
+
+
+impl Slugs {
+    ///Works out the slugs of the books, given by their paths in the bookshelf directory along with
+    /// whether they are files, and of every shelf they are on.
+    ///Slugs are claimed in name order so the same bookshelf always gets the same urls.
+    pub fn new<'a>(enabled: bool, books: impl IntoIterator<Item = (&'a Path, bool)>) -> Slugs {
+        if ! enabled {
+            return Slugs::default();
+        }
+
+        //every shelf is listed along with its books, parents sort before their children
+        let mut entries: BTreeMap<PathBuf, bool> = BTreeMap::new();
+        for (book, is_file) in books {
+            for shelf in book.ancestors().skip(1).filter(|shelf| ! shelf.as_os_str().is_empty()) {
+                entries.entry(shelf.to_path_buf()).or_insert(false);
+            }
+            entries.insert(book.components().collect(), is_file);
+        }
+
+        let mut paths: HashMap<PathBuf, PathBuf> = HashMap::new();
+        let mut taken: HashMap<PathBuf, HashMap<String, String>> = HashMap::new();
+
+        for (path, is_file) in entries {
+            let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+            let parent = path.parent()
+                             .and_then(|parent| paths.get(parent).cloned())
+                             .unwrap_or_default();
+
+            let slug = claim(taken.entry(parent.clone()).or_default(), slugify(&name, is_file), &name, is_file);
+            paths.insert(path, parent.join(slug));
+        }
+
+        Slugs { enabled, paths }
+    }
+
+
+    ///Path of a book or shelf in the build directory, relative to the bookshelf or shelves directory.
+    ///A trailing slash is kept.
+    pub fn url_path(&self, partial_path: &Path) -> PathBuf {
+        let key: PathBuf = partial_path.components().collect();
+
+        match self.paths.get(&key) {
+            Some(slugged) if partial_path.to_string_lossy().ends_with('/') => slugged.join(""),
+            Some(slugged) => slugged.clone(),
+            None => partial_path.to_path_buf(),
+        }
+    }
+
+
+    ///Url friendly version of a name placed next to books, such as the latest alias of a book.
+    pub fn name(&self, name: &str) -> String {
+        if self.enabled { slugify(name, false) } else { name.to_string() }
+    }
+
+
+    ///Directory names for names that share a directory, such as tags, keyed by the name. With slugs
+    /// they are url friendly, without they keep the name less the characters that would nest the
+    /// directory or cut its url short. Names that can't be a directory, like `..`, are left out.
+    pub fn names<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> HashMap<String, String> {
+        let mut taken = HashMap::new();
+
+        names.into_iter()
+             .filter(|name| ! matches!(name.trim(), "" | "." | ".."))
+             .map(|name| {
+                 let directory = if self.enabled { slugify(name, false) } else { safe_name(name) };
+                 (name.to_string(), claim(&mut taken, directory, name, false))
+             })
+             .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn slugs(books: &[(&str, bool)]) -> Slugs {
+        Slugs::new(true, books.iter().map(|(book, is_file)| (Path::new(*book), *is_file)))
+    }
+
+
+    #[test]
+    fn slugify_makes_names_url_friendly() {
+        assert_eq!(slugify("My Book (2nd ed).pdf", true), "my-book-2nd-ed.pdf");
+        assert_eq!(slugify("C# & .NET", false), "c-net");
+        assert_eq!(slugify("Économie Générale", false), "economie-generale");
+        assert_eq!(slugify("Spec v1.2.pdf", true), "spec-v1.2.pdf");
+        assert_eq!(slugify("Release 2.0", false), "release-2.0");
+        assert_eq!(slugify("日本語 ガイド", false), "日本語-ガイド");
+        assert_eq!(slugify("Ｆｕｌｌ ﬁle", false), "full-file");
+    }
+
+
+    #[test]
+    fn slugify_keeps_extensions_of_files_only() {
+        assert_eq!(slugify("Report.PDF", true), "report.pdf");
+        assert_eq!(slugify("guide.book", false), "guide-book");
+        assert_eq!(slugify("&.pdf", true), "untitled.pdf");
+        assert_eq!(slugify("&", false), "untitled");
+    }
+
+
+    #[test]
+    fn claim_numbers_later_clashes() {
+        let mut taken = HashMap::new();
+
+        assert_eq!(claim(&mut taken, "guide".to_string(), "Guide", false), "guide");
+        assert_eq!(claim(&mut taken, "guide".to_string(), "guide", false), "guide-2");
+        assert_eq!(claim(&mut taken, "guide".to_string(), "GUIDE", false), "guide-3");
+        assert_eq!(claim(&mut taken, "guide".to_string(), "Guide", false), "guide");
+
+        assert_eq!(claim(&mut taken, "spec.pdf".to_string(), "Spec.pdf", true), "spec.pdf");
+        assert_eq!(claim(&mut taken, "spec.pdf".to_string(), "spec.pdf", true), "spec-2.pdf");
+    }
+
+
+    #[test]
+    fn url_paths_slug_every_shelf() {
+        let slugs = slugs(&[("C# Stuff/Sub Shelf/My Book", false), ("C# Stuff/Spec (draft).pdf", true)]);
+
+        assert_eq!(slugs.url_path(Path::new("C# Stuff")), PathBuf::from("c-stuff"));
+        assert_eq!(slugs.url_path(Path::new("C# Stuff/Sub Shelf")), PathBuf::from("c-stuff/sub-shelf"));
+        assert_eq!(slugs.url_path(Path::new("C# Stuff/Sub Shelf/My Book")), PathBuf::from("c-stuff/sub-shelf/my-book"));
+        assert_eq!(slugs.url_path(Path::new("C# Stuff/Spec (draft).pdf")), PathBuf::from("c-stuff/spec-draft.pdf"));
+    }
+
+
+    #[test]
+    fn url_paths_keep_trailing_slashes_and_unknown_paths() {
+        let slugs = slugs(&[("Shelf/My Book", false)]);
+
+        assert_eq!(slugs.url_path(Path::new("Shelf/My Book/")).to_string_lossy(), "shelf/my-book/");
+        assert_eq!(slugs.url_path(Path::new("Other/Book")), PathBuf::from("Other/Book"));
+        assert_eq!(Slugs::default().url_path(Path::new("Shelf/My Book/")).to_string_lossy(), "Shelf/My Book/");
+    }
+
+
+    #[test]
+    fn url_paths_settle_clashes_the_same_way_every_time() {
+        let books = [("Shelf/my book.pdf", true), ("Shelf/My Book.pdf", true), ("shelf/Other.pdf", true)];
+        let slugs = slugs(&books);
+
+        //claimed in name order, so upper case comes first
+        assert_eq!(slugs.url_path(Path::new("Shelf/My Book.pdf")), PathBuf::from("shelf/my-book.pdf"));
+        assert_eq!(slugs.url_path(Path::new("Shelf/my book.pdf")), PathBuf::from("shelf/my-book-2.pdf"));
+        assert_eq!(slugs.url_path(Path::new("shelf/Other.pdf")), PathBuf::from("shelf-2/other.pdf"));
+
+        let reversed: Vec<(&str, bool)> = books.iter().rev().copied().collect();
+        let again = self::slugs(&reversed);
+        for (book, _) in books {
+            assert_eq!(again.url_path(Path::new(book)), slugs.url_path(Path::new(book)));
+        }
+    }
+
+
+    #[test]
+    fn names_leave_out_names_that_are_not_directories() {
+        for enabled in [false, true] {
+            let names = Slugs::new(enabled, []).names(["", " ", ".", "..", "...", "guides"]);
+
+            assert_eq!(names.len(), 2);
+            assert!(names.contains_key("..."));
+            assert!(names.contains_key("guides"));
+        }
+    }
+
+
+    #[test]
+    fn names_without_slugs_stay_in_one_directory() {
+        let names = Slugs::new(false, []).names(["a/b", "c\\d", "C#", "what?", "100%", "../up"]);
+
+        assert_eq!(names["a/b"], "a-b");
+        assert_eq!(names["c\\d"], "c-d");
+        assert_eq!(names["C#"], "C-");
+        assert_eq!(names["what?"], "what-");
+        assert_eq!(names["100%"], "100-");
+        assert_eq!(names["../up"], "..-up");
+    }
+
+
+    #[test]
+    fn names_claim_clashing_directories() {
+        let names = Slugs::new(false, []).names(["a-b", "a/b", "a\\b"]);
+
+        assert_eq!(names["a-b"], "a-b");
+        assert_eq!(names["a/b"], "a-b-2");
+        assert_eq!(names["a\\b"], "a-b-3");
+
+        let names = Slugs::new(true, []).names(["Rust", "rust", "RUST"]);
+
+        assert_eq!(names["Rust"], "rust");
+        assert_eq!(names["rust"], "rust-2");
+        assert_eq!(names["RUST"], "rust-3");
+    }
+}
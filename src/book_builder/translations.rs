@@ -0,0 +1,110 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use crate::book::{BookMetadata, Breadcrumb, TranslationPlacement};
+
+
+///A book that is one translation of a logical book.
+pub struct TranslationMember {
+    pub partial_path: PathBuf,
+    pub language: String,
+
+    ///name of the logical book when the book declared it, otherwise the book follows the
+    /// convention of being named after its language inside the logical book's directory
+    pub declared_name: Option<String>,
+
+    ///page the book's card opens, relative to the bookshelf build directory
+    pub page: PathBuf,
+}
+
+
+///Whether the book is named after its language, ie `guide/de/` or `guide/de.pdf`.
+pub fn named_after_language(partial_path: &Path, language: &str) -> bool {
+    let name = if partial_path.to_string_lossy().ends_with('/') {
+        partial_path.file_name()
+    } else {
+        partial_path.file_stem()
+    };
+
+    name.map(|name| name.to_string_lossy().eq_ignore_ascii_case(language))
+        .unwrap_or(false)
+}
+
+
+///Whether a language matches the preferred one, either exactly or by its primary subtag.
+fn matches_language(language: &str, preferred: &str) -> bool {
+    let primary = |language: &str| language.split(['-', '_']).next().unwrap_or_default().to_lowercase();
+
+    language.eq_ignore_ascii_case(preferred) || primary(language) == primary(preferred)
+}
+
+
+///Groups translations of the same logical book and tells every translation where it stands.
+///The card of a group opens the translation in the bookshelf's language, or the first
+/// translation by language code when there isn't one.
+pub fn place_translations(members: Vec<TranslationMember>, default_language: &str, bookshelf_directory: &Path) -> HashMap<PathBuf, TranslationPlacement> {
+    let mut groups: HashMap<(PathBuf, String), Vec<TranslationMember>> = HashMap::new();
+
+    for member in members {
+        let parent = member.partial_path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        //translations following the convention are grouped by the directory they share
+        let key = match &member.declared_name {
+            Some(name) => (parent, name.clone()),
+            None => {
+                let name = parent.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+                (parent, name)
+            },
+        };
+
+        groups.entry(key).or_default().push(member);
+    }
+
+    let url = |page: &Path| format!("{}/{}", bookshelf_directory.display(), page.display());
+
+    let mut placements = HashMap::new();
+
+    for ((parent, name), mut translations) in groups {
+        translations.sort_by_key(|translation| translation.language.to_lowercase());
+
+        let default = translations.iter()
+                                  .position(|translation| translation.language.eq_ignore_ascii_case(default_language))
+                                  .or_else(|| translations.iter().position(|translation| matches_language(&translation.language, default_language)))
+                                  .unwrap_or(0);
+
+        //the logical book's directory stands in for the translations on its parent shelf
+        let shelf_path = match translations[default].declared_name {
+            Some(_) => translations[default].partial_path.clone(),
+            None => parent.join(""),
+        };
+
+        let languages: Vec<Breadcrumb> = translations.iter()
+            .map(|translation| Breadcrumb { name: translation.language.clone(), url: url(&translation.page) })
+            .collect();
+
+        for (index, translation) in translations.iter().enumerate() {
+            placements.insert(translation.partial_path.clone(), TranslationPlacement {
+                name: name.clone(),
+                language: translation.language.clone(),
+                is_default: index == default,
+                languages: languages.clone(),
+                shelf_path: shelf_path.clone(),
+            });
+        }
+    }
+
+    placements
+}
+
+
+///Every language a translated book on the shelf is available in, for visitors to pick from.
+pub fn shelf_languages(books: &[BookMetadata]) -> Vec<String> {
+    books.iter()
+         .filter_map(|book| book.translation.as_ref())
+         .flat_map(|translation| translation.languages.iter().map(|language| language.name.clone()))
+         .collect::<BTreeSet<String>>()
+         .into_iter()
+         .collect()
+}
@@ -0,0 +1,301 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::book::{Breadcrumb, VersionPlacement};
+
+use super::hierarchy::natural_cmp;
+use super::injector::copy_dir_all;
+use super::slugs::Slugs;
+
+
+///Suffix of the alias the newest version of a book is copied to, ie `manual-latest/`.
+///Versions named only by their number, such as `v2/`, get an alias named just `latest/`.
+pub const LATEST_ALIAS: &str = "latest";
+
+///Suffix of the hidden file marking a path in the build directory as a latest alias, so only
+/// aliases from earlier builds are ever cleared, ie `.manual-latest.alias` next to `manual-latest/`.
+const ALIAS_MARKER: &str = "alias";
+
+
+///A book that is one version of a logical book.
+pub struct VersionMember {
+    pub partial_path: PathBuf,
+    ///where the book is placed in the bookshelf build directory
+    pub url_path: PathBuf,
+    pub name: String,
+    pub version: String,
+
+    ///page the book's card opens, relative to the bookshelf build directory
+    pub page: PathBuf,
+}
+
+
+///Splits a book's file or directory name following the `<name>-v<version>` convention, ie
+/// `manual-v2` or `spec_v1.3.pdf`. A name that is only a version, such as `v3`, gives an empty name.
+pub fn conventional_version(book_name: &str) -> Option<(String, String)> {
+    //a version such as v1.3 looks like an extension, real ones never start with a digit
+    let stem = match Path::new(book_name).extension().map(|extension| extension.to_string_lossy()) {
+        Some(extension) if ! extension.starts_with(|c: char| c.is_ascii_digit()) => {
+            book_name.trim_end_matches(&format!(".{}", extension))
+        },
+        _ => book_name,
+    };
+
+    let is_version = |candidate: &str| {
+        candidate.strip_prefix(['v', 'V'])
+                 .map(|number| number.starts_with(|c: char| c.is_ascii_digit()))
+                 .unwrap_or(false)
+    };
+
+    if is_version(stem) {
+        return Some( (String::new(), stem.to_string()) );
+    }
+
+    let split = stem.rfind(['-', '_', ' '])?;
+    let (name, version) = (&stem[..split], &stem[split + 1..]);
+
+    if is_version(version) && ! name.is_empty() {
+        Some( (name.to_string(), version.to_string()) )
+    } else {
+        None
+    }
+}
+
+
+///Path of the latest alias of a book, relative to the bookshelf build directory. It sits next to
+/// the versions so relative links inside the copied book still resolve.
+fn alias_path(latest: &VersionMember, slugs: &Slugs) -> PathBuf {
+    let alias_name = if latest.name.is_empty() {
+        LATEST_ALIAS.to_string()
+    } else {
+        slugs.name(&format!("{}-{}", latest.name, LATEST_ALIAS))
+    };
+
+    //mdbooks are directories and keep the trailing slash they are given, other books keep their extension
+    if latest.url_path.to_string_lossy().ends_with('/') {
+        let mut alias = latest.url_path.with_file_name(alias_name);
+        alias.push("");
+        return alias;
+    }
+
+    match latest.url_path.extension() {
+        Some(extension) => latest.url_path.with_file_name(format!("{}.{}", alias_name, extension.to_string_lossy())),
+        None => latest.url_path.with_file_name(alias_name),
+    }
+}
+
+
+///Groups versions of the same book on the same shelf, newest first, and tells every version where
+/// it stands. Each member comes with whether its book declared it in its settings, as books only
+/// following the naming convention need a sibling to form a group.
+///Books and the shelves holding them take up the given paths in the bookshelf build directory, a
+/// latest alias that would take the place of one of them is left out rather than overwrite it.
+pub fn place_versions(members: Vec<(VersionMember, bool)>, taken_paths: &HashSet<PathBuf>, bookshelf_directory: &Path, 
+                      slugs: &Slugs) -> HashMap<PathBuf, VersionPlacement> {
+    let mut groups: HashMap<(PathBuf, String), (Vec<VersionMember>, bool)> = HashMap::new();
+
+    for (member, declared) in members {
+        let shelf = member.partial_path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        let group = groups.entry( (shelf, member.name.clone()) ).or_insert_with(|| (vec!(), false));
+        group.0.push(member);
+        group.1 |= declared;
+    }
+
+    let url = |page: &Path| format!("{}/{}", bookshelf_directory.display(), page.display());
+
+    let mut placements = HashMap::new();
+
+    for ((shelf, name), (mut versions, declared)) in groups {
+        if versions.len() < 2 && ! declared {
+            continue;
+        }
+
+        versions.sort_by(|a, b| natural_cmp(&b.version, &a.version));
+
+        //the alias mirrors the newest version, so its card page sits at the same spot inside it
+        let latest = &versions[0];
+        let mut alias = Some(alias_path(latest, slugs));
+        
+        if let Some(clash) = alias.as_ref().filter(|alias| taken_paths.contains(&alias.components().collect::<PathBuf>())) {
+            log::error!("The latest alias of \"{}\" would replace {}, linking to version {} instead. Rename it to have the alias.",
+                        latest.partial_path.display(), clash.display(), latest.version);
+            alias = None;
+        }
+        
+        let alias_page = match &alias {
+            Some(alias) => match latest.page.strip_prefix(&latest.url_path) {
+                Ok(inner) if ! inner.as_os_str().is_empty() => alias.join(inner),
+                _ => alias.clone(),
+            },
+            None => latest.page.clone(),
+        };
+
+        let display_name = if name.is_empty() {
+            shelf.file_name().map(|shelf| shelf.to_string_lossy().to_string()).unwrap_or_default()
+        } else {
+            name
+        };
+
+        let latest = Breadcrumb { name: latest.version.clone(), url: url(&alias_page) };
+        let links: Vec<Breadcrumb> = versions.iter()
+            .map(|version| Breadcrumb { name: version.version.clone(), url: url(&version.page) })
+            .collect();
+
+        for (index, version) in versions.iter().enumerate() {
+            placements.insert(version.partial_path.clone(), VersionPlacement {
+                name: display_name.clone(),
+                version: version.version.clone(),
+                is_latest: index == 0,
+                latest: latest.clone(),
+                alias_path: alias.clone(),
+                alias_page: alias_page.clone(),
+                versions: links.clone(),
+            });
+        }
+    }
+
+    placements
+}
+
+
+///Path of the marker file of a latest alias.
+fn alias_marker(alias_build_path: &Path) -> PathBuf {
+    let name = alias_build_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    alias_build_path.with_file_name(format!(".{}.{}", name, ALIAS_MARKER))
+}
+
+
+///Copies the newest version of a book to its stable latest alias once it has been built.
+///Anything already at the alias's path is only cleared when an earlier build made it as the alias.
+pub fn create_latest_alias(book_build_path: &Path, alias_build_path: &Path) {
+    let marker = alias_marker(alias_build_path);
+
+    if alias_build_path.exists() {
+        if ! marker.exists() {
+            log::error!("{} is in the way of a latest alias and wasn't made as one, leaving it be.", alias_build_path.display());
+            return;
+        }
+
+        let removed = if alias_build_path.is_dir() { fs::remove_dir_all(alias_build_path) } else { fs::remove_file(alias_build_path) };
+
+        if let Err(err) = removed {
+            log::error!("Unable to clear the old latest alias {}: {}", alias_build_path.display(), err);
+            return;
+        }
+    }
+
+    let copied = if book_build_path.is_dir() {
+        copy_dir_all(book_build_path, alias_build_path)
+    } else {
+        fs::copy(book_build_path, alias_build_path).map(|_| ()).map_err(anyhow::Error::from)
+    };
+
+    let marked = copied.and_then(|_| fs::write(&marker, "").map_err(anyhow::Error::from));
+
+    if let Err(err) = marked {
+        log::error!("Unable to create the latest alias {}: {:#}", alias_build_path.display(), err);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn member(partial_path: &str, name: &str, version: &str) -> (VersionMember, bool) {
+        let member = VersionMember {
+            partial_path: PathBuf::from(partial_path),
+            url_path: PathBuf::from(partial_path),
+            name: name.to_string(),
+            version: version.to_string(),
+            page: PathBuf::from(partial_path),
+        };
+
+        (member, false)
+    }
+
+
+    #[test]
+    fn conventional_version_splits_names() {
+        let split = |name: &str, version: &str| Some( (name.to_string(), version.to_string()) );
+
+        assert_eq!(conventional_version("manual-v2"), split("manual", "v2"));
+        assert_eq!(conventional_version("spec_v1.3.pdf"), split("spec", "v1.3"));
+        assert_eq!(conventional_version("My Guide V10.pdf"), split("My Guide", "V10"));
+        assert_eq!(conventional_version("v3"), split("", "v3"));
+        assert_eq!(conventional_version("v1.2"), split("", "v1.2"));
+    }
+
+
+    #[test]
+    fn conventional_version_needs_a_number() {
+        assert_eq!(conventional_version("manual"), None);
+        assert_eq!(conventional_version("manual-latest"), None);
+        assert_eq!(conventional_version("overview.pdf"), None);
+        assert_eq!(conventional_version("-v2"), None);
+        assert_eq!(conventional_version("vintage"), None);
+    }
+
+
+    #[test]
+    fn versions_are_ordered_newest_first() {
+        let members = vec!(member("shelf/manual-v2.pdf", "manual", "v2"), member("shelf/manual-v10.pdf", "manual", "v10"));
+        let placements = place_versions(members, &HashSet::new(), Path::new("bookshelf"), &Slugs::default());
+
+        let placement = &placements[Path::new("shelf/manual-v10.pdf")];
+        assert!(placement.is_latest);
+        assert_eq!(placement.alias_path, Some(PathBuf::from("shelf/manual-latest.pdf")));
+        assert_eq!(placement.latest.url, "bookshelf/shelf/manual-latest.pdf");
+
+        let versions: Vec<&str> = placement.versions.iter().map(|version| version.name.as_str()).collect();
+        assert_eq!(versions, ["v10", "v2"]);
+    }
+
+
+    #[test]
+    fn latest_alias_never_replaces_a_book() {
+        let members = vec!(member("shelf/manual-v1/", "manual", "v1"), member("shelf/manual-v2/", "manual", "v2"));
+        let taken_paths = HashSet::from([PathBuf::from("shelf/manual-latest")]);
+        let placements = place_versions(members, &taken_paths, Path::new("bookshelf"), &Slugs::default());
+
+        let placement = &placements[Path::new("shelf/manual-v2/")];
+        assert_eq!(placement.alias_path, None);
+        assert_eq!(placement.latest.url, "bookshelf/shelf/manual-v2/");
+    }
+
+
+    #[test]
+    fn latest_alias_never_replaces_a_shelf() {
+        let members = vec!(member("Manuals/v1/", "", "v1"), member("Manuals/v2/", "", "v2"));
+        let taken_paths = HashSet::from([PathBuf::from("Manuals"), PathBuf::from("Manuals/latest"), PathBuf::from("Manuals/latest/inner")]);
+        let placements = place_versions(members, &taken_paths, Path::new("bookshelf"), &Slugs::default());
+
+        assert_eq!(placements[Path::new("Manuals/v2/")].alias_path, None);
+    }
+
+
+    #[test]
+    fn only_earlier_aliases_are_cleared() {
+        let build = tempfile::tempdir().unwrap();
+        let book = build.path().join("manual-v2.pdf");
+        let alias = build.path().join("manual-latest.pdf");
+        fs::write(&book, "v2").unwrap();
+
+        //something the bookshelf didn't make is left alone
+        fs::write(&alias, "other").unwrap();
+        create_latest_alias(&book, &alias);
+        assert_eq!(fs::read_to_string(&alias).unwrap(), "other");
+
+        //an alias from an earlier build is brought up to date
+        fs::remove_file(&alias).unwrap();
+        create_latest_alias(&book, &alias);
+        fs::write(&book, "v3").unwrap();
+        create_latest_alias(&book, &alias);
+        assert_eq!(fs::read_to_string(&alias).unwrap(), "v3");
+    }
+}
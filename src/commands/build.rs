@@ -6,9 +6,17 @@ use clap::{Command, ArgMatches};
 
 use crate::book_builder::build_bookshelf;
 use crate::page_builder::build_pages;
+use crate::config::Config;
+use crate::page_builder::catalog::Catalog;
+use crate::page_builder::theme::Theme;
 
 
-pub fn build_bookshelf_cmd() {
+pub fn build_bookshelf_cmd() -> Result<(), anyhow::Error> {
+
+    //load the bookshelf.toml from the project root if there is one
+    let config = Config::load(&std::env::current_dir()?)?;
+    let theme = Theme::new(&config);
+    let catalog = Catalog::load(&config, &theme);
 
     //figure out the root source and build paths and define the bookshelf directory
     //>Note pushing "" forces PathBuf to add a trailing /. only easy way I could find to do it.
@@ -18,11 +26,12 @@ pub fn build_bookshelf_cmd() {
     let build_path = std::env::current_dir().unwrap().join("build").join("");
     
     //Compile book hierarchy and build all books into the build directory
-    let bookshelf_metadata = build_bookshelf(source_path, build_path, bookshelf_directory);
+    let bookshelf_metadata = build_bookshelf(&config, &theme, &catalog, source_path, build_path, bookshelf_directory);
     
     //Use book hierarchy data to build an index page that links to everything.
-    build_pages(bookshelf_metadata);
+    build_pages(&config, bookshelf_metadata, &theme, &catalog);
 
+    Ok(())
 }
 
 // Create clap subcommand arguments for build
@@ -35,7 +44,5 @@ pub fn make_subcommand_build<'help>() -> Command<'help> {
 // Build command implementation
 pub fn execute_build(_args: &ArgMatches) -> Result<(), anyhow::Error> {
 
-    build_bookshelf_cmd();
-
-    Ok(())
+    build_bookshelf_cmd()
 }
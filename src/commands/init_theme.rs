@@ -0,0 +1,52 @@
+use std::fs;
+
+
+use anyhow::Context;
+use clap::{Command, Arg, ArgMatches};
+
+
+use crate::config::Config;
+use crate::page_builder::theme::default_files;
+
+
+// Create clap subcommand arguments
+pub fn make_subcommand_init_theme<'help>() -> Command<'help> {
+    Command::new("init-theme")
+        .about("Writes the default templates, stylesheets and catalogs into the theme directory for editing")
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .help("Overwrite files that are already in the theme directory"),
+        )
+}
+
+// Init theme command implementation
+pub fn execute_init_theme(args: &ArgMatches) -> Result<(), anyhow::Error> {
+
+    let config = Config::load(&std::env::current_dir()?)?;
+    let theme_path = config.root.join(&config.bookshelf.theme_directory);
+    let force = args.is_present("force");
+
+    for (name, contents) in default_files() {
+        let path = theme_path.join(name);
+
+        //never lose someone's edits unless they asked for it
+        if path.exists() && ! force {
+            log::info!("Keeping existing {}", path.display());
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Unable to create {}", parent.display()))?;
+        }
+
+        fs::write(&path, contents)
+            .with_context(|| format!("Unable to write {}", path.display()))?;
+        log::info!("Wrote {}", path.display());
+    }
+
+    log::info!("Theme written to {}. Delete any file you don't change to keep using the default.", theme_path.display());
+
+    Ok(())
+}
@@ -3,4 +3,5 @@
 pub mod build;
 pub mod serve;
 pub mod clean;
+pub mod init_theme;
 
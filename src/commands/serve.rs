@@ -11,10 +11,12 @@ use futures_util::sink::SinkExt;
 use futures_util::StreamExt;
 use warp::ws::Message;
 use warp::Filter;
+use warp::filters::BoxedFilter;
 
 use log;
 
 use super::build::build_bookshelf_cmd;
+use crate::config::Config;
 
 
 // Create clap subcommand arguments
@@ -26,9 +28,12 @@ pub fn make_subcommand_serve<'help>() -> Command<'help> {
 // Serve command implementation
 pub fn execute_serve(_args: &ArgMatches) -> Result<(), anyhow::Error> {
 
-    build_bookshelf_cmd();
+    build_bookshelf_cmd()?;
 
-    spawn_server("./build".to_string(), "127.0.0.1", "3000");
+    //the site is served under the path it will be hosted at so its links work the same
+    let config = Config::load(&std::env::current_dir()?)?;
+
+    spawn_server("./build".to_string(), "127.0.0.1", "3000", &config);
 
     
     //loop{} //loop until Ctrl+C is ran.
@@ -42,34 +47,31 @@ pub fn execute_serve(_args: &ArgMatches) -> Result<(), anyhow::Error> {
 const LIVE_RELOAD_ENDPOINT: &str = "__livereload";
 
 //Spawn a basic static server
-pub fn spawn_server(build_dir: String, hostname: &str, port: &str) {
+pub fn spawn_server(build_dir: String, hostname: &str, port: &str, config: &Config) {
 
     let address = format!("{}:{}", hostname, port);
 
     let sockaddr: SocketAddr = address
         .to_socket_addrs()
-        .expect(format!("{} is an invalid socket address.", address).as_str())
+        .unwrap_or_else(|_| panic!("{} is an invalid socket address.", address))
         .next()
         .ok_or_else(|| anyhow::anyhow!("no address found for {}", address))
         .expect("no address found");
         
-    /*let input_404 = book
-        .config
-        .get("output.html.input-404")
-        .map(toml::Value::as_str)
-        .and_then(std::convert::identity) // flatten
-        .map(ToString::to_string);
-    let file_404 = get_404_output_file(&input_404);*/
+    //the bookshelf's own not found page is served for anything missing
+    let file_404 = config.bookshelf.not_found_page.clone();
+    let base_path = config.bookshelf.base_path();
 
     // A channel used to broadcast to any websockets to reload when a file changes.
     let (tx, _rx) = tokio::sync::broadcast::channel::<Message>(100);
 
     let reload_tx = tx.clone();
+    let served_path = base_path.clone();
     let thread_handle = std::thread::spawn(move || {
-        serve(PathBuf::from(build_dir), sockaddr, reload_tx, "error404.html");
+        serve(PathBuf::from(build_dir), sockaddr, reload_tx, &file_404, &served_path);
     });
 
-    let serving_url = format!("http://{}", address);
+    let serving_url = format!("http://{}{}", address, base_path);
     log::info!("Serving on: {}", serving_url);
     
     //wait forever until program is closed with Ctrl+C
@@ -85,6 +87,7 @@ async fn serve(
     address: SocketAddr,
     reload_tx: broadcast::Sender<Message>,
     file_404: &str,
+    base_path: &str,
 ) {
     // A warp Filter which captures `reload_tx` and provides an `rx` copy to
     // receive reload messages.
@@ -106,8 +109,15 @@ async fn serve(
                 }
             })
         });
+    // Only requests under the base path reach the files, with the base path taken off.
+    let base = base_path.split('/')
+        .filter(|segment| ! segment.is_empty())
+        .fold(warp::any().boxed(), |base: BoxedFilter<()>, segment| {
+            let segment: &'static str = Box::leak(segment.to_string().into_boxed_str());
+            base.and(warp::path(segment)).boxed()
+        });
     // A warp Filter that serves from the filesystem.
-    let book_route = warp::fs::dir(build_dir.clone());
+    let book_route = base.and(warp::fs::dir(build_dir.clone()));
     // The fallback route for 404 errors
     let fallback_route = warp::fs::file(build_dir.join(file_404))
         .map(|reply| warp::reply::with_status(reply, warp::http::StatusCode::NOT_FOUND));
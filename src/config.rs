@@ -0,0 +1,386 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    collections::{BTreeMap, HashMap},
+    fs,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use toml::value::Table;
+
+
+///Name of the configuration file looked for in the project root.
+pub const CONFIG_FILE: &str = "bookshelf.toml";
+
+///Name of the optional configuration file inside a shelf directory.
+pub const SHELF_CONFIG_FILE: &str = "shelf.toml";
+
+
+///Top level bookshelf configuration. Every section and key is optional so a missing or
+/// partial config file falls back to the defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    ///Directory the config was loaded from. Relative paths in the config are resolved against it.
+    #[serde(skip)]
+    pub root: PathBuf,
+    
+    pub bookshelf: BookshelfConfig,
+    
+    pub sandbox: SandboxConfig,
+    
+    ///Replacements for individual strings of the bookshelf's own pages, keyed like the built-in catalogs.
+    pub ui_strings: HashMap<String, String>,
+    
+    ///Settings shaped like a book.toml that are merged over every mdBook's own configuration.
+    pub mdbook: Table,
+}
+
+
+///Settings found under the `[bookshelf]` table.
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct BookshelfConfig {
+    ///Name of the site, shown on the index page and in every book's navigation bar.
+    pub title: String,
+    
+    ///Directory whose files override the built-in templates, stylesheets and catalogs file by file.
+    pub theme_directory: PathBuf,
+    
+    ///Language of the bookshelf's own pages, picking the catalog their strings come from.
+    ///Also the translation a book's card opens by default.
+    pub language: String,
+    
+    ///Follow symlinks while indexing the source directory. Links resolving outside the
+    /// source directory are never followed, and without this no symlink is used at all.
+    pub follow_symlinks: bool,
+    
+    ///Directory of files shared by every mdBook, reachable from books through the `@common/` alias.
+    pub common_directory: Option<PathBuf>,
+    
+    ///How books and shelves are ordered unless a shelf says otherwise.
+    pub sort: SortKey,
+    
+    ///Heading of the shelf holding books placed directly in the bookshelf directory.
+    pub root_shelf_name: String,
+    ///Where that shelf goes on the index page.
+    pub root_shelf_position: RootShelfPosition,
+    
+    ///Colour theme visitors see until they pick their own, also given to every mdBook.
+    pub color_theme: ColorTheme,
+    
+    ///Where the site is hosted, either a path such as `/docs/` or a full url. Every generated link
+    /// starts with it and mdBooks are given their own url below it.
+    pub site_url: String,
+    ///Link pages to each other relatively instead, so the build directory can be opened from disk.
+    pub relative_links: bool,
+    ///Give books, shelves and tags url friendly paths in the build directory, ie `c-net/my-book-2nd-ed.pdf`
+    /// for `C# & .NET/My Book (2nd ed).pdf`. Their original names are still shown.
+    pub slug_urls: bool,
+    
+    ///Add the text of every PDF page to the search index. Extracting it slows down the build, and
+    /// the extractor prints notes about fonts it can't map to the console.
+    pub search_pdf_text: bool,
+    
+    ///Fail the build when a `shelf:` link leads to no book on the shelf, rather than leaving it as
+    /// it is and only reporting it.
+    pub strict_shelf_links: bool,
+    
+    ///File name of the page shown for missing addresses, written to the build directory.
+    ///Most static hosts and `serve` look for `404.html`.
+    pub not_found_page: String,
+}
+
+
+///Bookshelf settings for a single book. mdBooks give these under `[bookshelf]` in their book.toml,
+/// other books in a sidecar file named after the book with `.toml` added, ie `spec.pdf.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct BookSettings {
+    ///Position of the book on its shelf when sorted by weight.
+    pub weight: Option<i64>,
+    ///Tags placing the book on virtual shelves next to its own.
+    pub tags: Vec<String>,
+    ///Name of the series the book is a volume of.
+    pub series: Option<String>,
+    ///Position of the book in its series, lowest first.
+    pub volume: Option<i64>,
+    ///Name of the logical book this is a version of. Versions are grouped with their siblings.
+    pub version_of: Option<String>,
+    ///Version label, compared naturally so the highest is the newest. Defaults to the file or directory name.
+    pub version: Option<String>,
+    ///Name of the logical book this is a translation of. Translations are grouped with their siblings.
+    pub translation_of: Option<String>,
+    ///Language of a book that has no config of its own. mdBooks use `book.language` instead.
+    pub language: Option<String>,
+}
+
+
+///What books and shelves are ordered by.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortKey {
+    ///Directory or file name, ignoring case.
+    Name,
+    ///Directory or file name with numbers compared by value, so "Part 9" comes before "Part 10".
+    #[default]
+    Natural,
+    ///Book title, shelves use their name.
+    Title,
+    ///Most recently modified first.
+    Date,
+    ///Explicit weights from shelf.toml or book.toml, lowest first. Unweighted entries go last.
+    Weight,
+}
+
+
+///Where the shelf of books placed directly in the bookshelf directory is shown.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RootShelfPosition {
+    ///Before every other shelf, for featured books.
+    First,
+    ///After every other shelf, for books that haven't been sorted onto one yet.
+    #[default]
+    Last,
+}
+
+
+///Built-in colour themes of the bookshelf's pages.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorTheme {
+    ///Light or dark following the visitor's `prefers-color-scheme`.
+    #[default]
+    Auto,
+    Light,
+    Dark,
+    ///White on black with strong borders.
+    HighContrast,
+}
+
+impl ColorTheme {
+    ///The mdBook theme closest to this one. Auto leaves the choice to mdBook, which also follows
+    /// the visitor's `prefers-color-scheme`.
+    pub fn mdbook_theme(self) -> Option<&'static str> {
+        match self {
+            ColorTheme::Auto => None,
+            ColorTheme::Light => Some("light"),
+            ColorTheme::Dark | ColorTheme::HighContrast => Some("coal"),
+        }
+    }
+}
+
+
+///Settings from a `shelf.toml` placed in a shelf directory.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ShelfConfig {
+    ///Order of this shelf's books and sub-shelves. Sub-shelves inherit it unless they set their own.
+    pub sort: Option<SortKey>,
+    ///Position of this shelf among its siblings when sorted by weight.
+    pub weight: Option<i64>,
+    ///Weights of the books and sub-shelves in this shelf, keyed by file or directory name.
+    pub weights: HashMap<String, i64>,
+    ///Series of books on this shelf, keyed by series name and listing file or directory names in reading order.
+    ///Kept in name order so a book listed in two series always ends up in the same one.
+    pub series: BTreeMap<String, Vec<String>>,
+}
+
+
+///How much of a book's configuration is trusted to run external commands.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SandboxMode {
+    ///Books may run any preprocessor or renderer command, as plain mdBook does.
+    #[default]
+    Trusted,
+    ///Books may only run the commands listed in `allowed-commands`.
+    Allowlist,
+    ///Books may not run any external commands. mdBook's built-in preprocessors and renderers still work.
+    NoCommands,
+}
+
+
+///What to do with a book that declares a command the sandbox does not allow.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SandboxViolation {
+    ///Build the book without the disallowed preprocessors and renderers.
+    #[default]
+    Strip,
+    ///Leave the book off the bookshelf entirely.
+    Skip,
+}
+
+
+///Settings found under the `[sandbox]` table. These only apply to commands declared by the
+/// books themselves, anything configured in the bookshelf config is trusted.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SandboxConfig {
+    pub mode: SandboxMode,
+    pub allowed_commands: Vec<String>,
+    pub on_violation: SandboxViolation,
+}
+
+
+impl Default for BookshelfConfig {
+    fn default() -> Self {
+        BookshelfConfig {
+            title: "Library".to_string(),
+            theme_directory: PathBuf::from("theme"),
+            language: "en".to_string(),
+            follow_symlinks: false,
+            common_directory: None,
+            sort: SortKey::default(),
+            root_shelf_name: "Unshelved".to_string(),
+            root_shelf_position: RootShelfPosition::default(),
+            color_theme: ColorTheme::default(),
+            site_url: "/".to_string(),
+            relative_links: false,
+            slug_urls: false,
+            search_pdf_text: true,
+            strict_shelf_links: true,
+            not_found_page: "404.html".to_string(),
+        }
+    }
+}
+
+
+impl BookshelfConfig {
+    ///The site url with a trailing slash, so links can be added straight onto it.
+    ///A bare path such as `docs` is taken from the root of the host.
+    pub fn site_root(&self) -> String {
+        let url = self.site_url.trim_end_matches('/');
+
+        if url.is_empty() || url.contains("://") || url.starts_with('/') {
+            format!("{}/", url)
+        } else {
+            format!("/{}/", url)
+        }
+    }
+
+
+    ///The path part of the site url, ie `/docs/` for `https://host/docs/`.
+    pub fn base_path(&self) -> String {
+        let root = self.site_root();
+
+        match root.split_once("://") {
+            Some((_, host_and_path)) => host_and_path.find('/').map(|path| host_and_path[path..].to_string()).unwrap_or_else(|| "/".to_string()),
+            None => root,
+        }
+    }
+}
+
+
+
+impl Config {
+    ///Loads the config file from the given project root, or the defaults if there is none.
+    pub fn load(root: &Path) -> Result<Config, anyhow::Error> {
+        let config_path = root.join(CONFIG_FILE);
+
+        if ! config_path.exists() {
+            log::debug!("No {} found in {}, using default configuration.", CONFIG_FILE, root.display());
+            return Ok(Config { root: root.to_path_buf(), ..Default::default() });
+        }
+
+        let raw = fs::read_to_string(&config_path)
+            .with_context(|| format!("Unable to read {}", config_path.display()))?;
+
+        let mut config: Config = toml::from_str(&raw)
+            .with_context(|| format!("Invalid configuration in {}", config_path.display()))?;
+        config.root = root.to_path_buf();
+
+        //the page is written into the build directory, a path could place it anywhere
+        let not_found_page = Path::new(&config.bookshelf.not_found_page);
+        if ! matches!(not_found_page.components().collect::<Vec<_>>().as_slice(), [Component::Normal(_)]) {
+            return Err(anyhow::anyhow!("not-found-page must be a file name, not \"{}\", in {}", 
+                                       config.bookshelf.not_found_page, config_path.display()));
+        }
+
+        Ok(config)
+    }
+}
+
+
+impl ShelfConfig {
+    ///Loads the shelf.toml from a shelf directory. A missing or broken file gives the defaults
+    /// so one bad shelf doesn't stop the whole bookshelf from building.
+    pub fn load(shelf_dir: &Path) -> ShelfConfig {
+        let config_path = shelf_dir.join(SHELF_CONFIG_FILE);
+
+        if ! config_path.exists() {
+            return ShelfConfig::default();
+        }
+
+        let parsed = fs::read_to_string(&config_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|raw| toml::from_str(&raw).map_err(anyhow::Error::from));
+
+        match parsed {
+            Ok(shelf_config) => shelf_config,
+            Err(err) => {
+                log::error!("Ignoring invalid {}: {}", config_path.display(), err);
+                ShelfConfig::default()
+            },
+        }
+    }
+}
+
+
+impl BookSettings {
+    ///Loads the sidecar settings file of a book that has no config of its own, such as a PDF.
+    pub fn load_sidecar(book_path: &Path) -> BookSettings {
+        let mut sidecar_name = book_path.as_os_str().to_os_string();
+        sidecar_name.push(".toml");
+        let sidecar_path = PathBuf::from(sidecar_name);
+
+        if ! sidecar_path.exists() {
+            return BookSettings::default();
+        }
+
+        let parsed = fs::read_to_string(&sidecar_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|raw| toml::from_str(&raw).map_err(anyhow::Error::from));
+
+        match parsed {
+            Ok(settings) => settings,
+            Err(err) => {
+                log::error!("Ignoring invalid {}: {}", sidecar_path.display(), err);
+                BookSettings::default()
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn bookshelf(site_url: &str) -> BookshelfConfig {
+        BookshelfConfig { site_url: site_url.to_string(), ..Default::default() }
+    }
+
+
+    #[test]
+    fn site_root_ends_in_one_slash() {
+        assert_eq!(bookshelf("/").site_root(), "/");
+        assert_eq!(bookshelf("").site_root(), "/");
+        assert_eq!(bookshelf("docs").site_root(), "/docs/");
+        assert_eq!(bookshelf("/docs//").site_root(), "/docs/");
+        assert_eq!(bookshelf("https://example.com/docs").site_root(), "https://example.com/docs/");
+        assert_eq!(bookshelf("https://example.com").site_root(), "https://example.com/");
+    }
+
+
+    #[test]
+    fn base_path_drops_the_host() {
+        assert_eq!(bookshelf("/").base_path(), "/");
+        assert_eq!(bookshelf("docs/team").base_path(), "/docs/team/");
+        assert_eq!(bookshelf("https://example.com/docs/").base_path(), "/docs/");
+        assert_eq!(bookshelf("https://example.com").base_path(), "/");
+    }
+}
@@ -4,10 +4,10 @@ extern crate clap;
 use clap::{Command, Arg}; //arg, ArgMatches
 use clap_complete::Shell;
 use anyhow::anyhow;
-use log;
 
 
 mod book;
+mod config;
 mod book_builder;
 mod page_builder;
 mod commands;
@@ -36,6 +36,7 @@ fn main() {
             .subcommand(commands::build::make_subcommand_build())
             .subcommand(commands::serve::make_subcommand_serve())
             .subcommand(commands::clean::make_subcommand_clean())
+            .subcommand(commands::init_theme::make_subcommand_init_theme())
             .subcommand(
                 Command::new("completions")
                     .about("Generate shell completions for your shell to stdout")
@@ -57,6 +58,7 @@ fn main() {
         Some(("build", sub_matches)) => commands::build::execute_build(sub_matches),
         Some(("serve", sub_matches)) => commands::serve::execute_serve(sub_matches),
         Some(("clean", sub_matches)) => commands::clean::execute_clean(sub_matches),
+        Some(("init-theme", sub_matches)) => commands::init_theme::execute_init_theme(sub_matches),
         Some(("completions", sub_matches)) => (|| {
             let shell: Shell = sub_matches
                 .value_of("shell")
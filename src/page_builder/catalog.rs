@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use handlebars::{Handlebars, Helper, Context, RenderContext, Output, HelperResult, RenderError, html_escape};
+
+use crate::config::Config;
+
+use super::theme::{Theme, CATALOGS_DIRECTORY};
+
+
+///Catalog every other one is laid over, so a string missing from a translation is still shown.
+const FALLBACK_LANGUAGE: &str = "en";
+
+
+///Translated strings for the bookshelf's own pages and the navigation added to mdBooks.
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    strings: HashMap<String, String>,
+}
+
+
+///Parses the catalog for a language from the theme, which has the built-in ones unless overridden.
+fn theme_catalog(theme: &Theme, language: &str) -> Option<HashMap<String, String>> {
+    let raw = theme.file(&format!("{}/{}.toml", CATALOGS_DIRECTORY, language))?;
+
+    match toml::from_str(&String::from_utf8_lossy(&raw)) {
+        Ok(strings) => Some(strings),
+        Err(err) => {
+            log::error!("Catalog {} is invalid: {}", language, err);
+            None
+        },
+    }
+}
+
+
+impl Catalog {
+    ///Loads the catalog for the configured language over the fallback one, then applies the
+    /// `[ui-strings]` from the config. A regional language such as `de-AT` uses the `de` catalog.
+    pub fn load(config: &Config, theme: &Theme) -> Catalog {
+        let language = &config.bookshelf.language;
+        let mut strings = theme_catalog(theme, FALLBACK_LANGUAGE).unwrap_or_default();
+
+        let primary = language.split(['-', '_']).next().unwrap_or_default().to_lowercase();
+        match theme_catalog(theme, language).or_else(|| theme_catalog(theme, &primary)) {
+            Some(translated) => strings.extend(translated),
+            None => log::warn!("No catalog for language \"{}\", using English. Strings can be translated under [ui-strings].", language),
+        }
+
+        for (key, value) in &config.ui_strings {
+            if ! strings.contains_key(key) {
+                log::warn!("Unknown ui string \"{}\" in the config.", key);
+            }
+            strings.insert(key.clone(), value.clone());
+        }
+
+        Catalog { strings }
+    }
+
+
+    ///Looks up a string and fills in its numbered placeholders. Unknown keys are returned as is.
+    ///When the first value is 1 a `<key>-one` string is used if the catalog has one, for counts.
+    pub fn translate(&self, key: &str, args: &[String]) -> String {
+        let singular = match args.first() {
+            Some(count) if count == "1" => self.strings.get(&format!("{}-one", key)),
+            _ => None,
+        };
+        
+        let mut translated = match singular.or_else(|| self.strings.get(key)) {
+            Some(string) => string.clone(),
+            None => {
+                log::warn!("Missing ui string \"{}\".", key);
+                key.to_string()
+            },
+        };
+
+        for (index, arg) in args.iter().enumerate() {
+            translated = translated.replace(&format!("{{{}}}", index), arg);
+        }
+
+        translated
+    }
+
+
+    ///Adds the `t` helper to the templates, ie `{{t "welcome" site_title}}`.
+    ///Values given to the helper are escaped, the catalog strings themselves are not.
+    pub fn register(&self, handlebars: &mut Handlebars) {
+        let catalog = self.clone();
+
+        handlebars.register_helper("t", Box::new(
+            move |helper: &Helper, _: &Handlebars, _: &Context, _: &mut RenderContext, out: &mut dyn Output| -> HelperResult {
+                let key = helper.param(0)
+                                .and_then(|key| key.value().as_str())
+                                .ok_or_else(|| RenderError::new("The t helper needs a string key."))?;
+
+                let args: Vec<String> = helper.params()
+                    .iter()
+                    .skip(1)
+                    .map(|param| match param.value() {
+                        serde_json::Value::String(string) => html_escape(string),
+                        other => html_escape(&other.to_string()),
+                    })
+                    .collect();
+
+                out.write(&catalog.translate(key, &args))?;
+                Ok(())
+            }
+        ));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn catalog(strings: &[(&str, &str)]) -> Catalog {
+        Catalog { strings: strings.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect() }
+    }
+
+
+    #[test]
+    fn counts_of_one_use_the_singular() {
+        let catalog = catalog(&[("pages", "{0} pages"), ("pages-one", "{0} page"), ("volumes", "{0} volumes")]);
+
+        assert_eq!(catalog.translate("pages", &["1".to_string()]), "1 page");
+        assert_eq!(catalog.translate("pages", &["2".to_string()]), "2 pages");
+        assert_eq!(catalog.translate("pages", &["0".to_string()]), "0 pages");
+        assert_eq!(catalog.translate("pages", &["11".to_string()]), "11 pages");
+        assert_eq!(catalog.translate("volumes", &["1".to_string()]), "1 volumes");
+    }
+
+
+    #[test]
+    fn placeholders_are_filled_and_unknown_keys_kept() {
+        let catalog = catalog(&[("welcome", "{1}, welcome to {0} ({0})")]);
+
+        assert_eq!(catalog.translate("welcome", &["Library".to_string(), "Hi".to_string()]), "Hi, welcome to Library (Library)");
+        assert_eq!(catalog.translate("missing", &[]), "missing");
+    }
+
+
+    #[test]
+    fn regional_languages_use_their_catalog_under_the_config() {
+        let mut config = Config::default();
+        config.bookshelf.language = "de-AT".to_string();
+        config.ui_strings.insert("tags".to_string(), "Themen".to_string());
+
+        let catalog = Catalog::load(&config, &Theme::default());
+
+        assert_eq!(catalog.translate("shelves", &[]), "Regale");
+        assert_eq!(catalog.translate("pages", &["1".to_string()]), "1 Seite");
+        assert_eq!(catalog.translate("tags", &[]), "Themen");
+    }
+
+
+    #[test]
+    fn helper_escapes_values_only() {
+        let mut handlebars = Handlebars::new();
+        catalog(&[("by", "<em>by</em> {0}")]).register(&mut handlebars);
+
+        let rendered = handlebars.render_template(r#"{{t "by" name}}"#, &serde_json::json!({ "name": "<Ann & Bo>" })).unwrap();
+        assert_eq!(rendered, "<em>by</em> &lt;Ann &amp; Bo&gt;");
+    }
+}
@@ -0,0 +1,219 @@
+use chrono::DateTime;
+use handlebars::{Handlebars, Helper, Context, RenderContext, Output, HelperResult, handlebars_helper, html_escape};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde_json::Value;
+
+use mdbook::utils::render_markdown;
+
+
+///Characters escaped in a url path. Slashes are kept so whole paths can be encoded at once.
+const PATH_ESCAPES: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>')
+                                         .add(b'?').add(b'`').add(b'{').add(b'}');
+
+///Units used when formatting byte sizes.
+const SIZE_UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+
+//Formats seconds since the unix epoch, such as a book's modified time, ie `{{date this.modified format="%d %B %Y"}}`.
+handlebars_helper!(date: |seconds: i64, { format: str = "%Y-%m-%d" }| {
+    DateTime::from_timestamp(seconds, 0)
+        .map(|time| time.format(format).to_string())
+        .unwrap_or_default()
+});
+
+//Formats a size in bytes with the largest unit that keeps it at one or more, ie `{{bytes this.size}}` gives "2.4 MB".
+handlebars_helper!(bytes: |size: u64| {
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < SIZE_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", size, SIZE_UNITS[0])
+    } else {
+        format!("{:.1} {}", value, SIZE_UNITS[unit])
+    }
+});
+
+//Picks the singular or plural form for a count, ie `{{count}} {{pluralize count "volume" "volumes"}}`.
+//The plural defaults to the singular with an s added.
+handlebars_helper!(pluralize: |count: i64, singular: str, *args| {
+    if count == 1 {
+        singular.to_string()
+    } else {
+        args.get(2)
+            .and_then(|plural| plural.as_str())
+            .map(ToString::to_string)
+            .unwrap_or_else(|| format!("{}s", singular))
+    }
+});
+
+//Joins a list such as a book's authors, ie `{{join this.authors}}` gives "Ann, Bo and Cy".
+//The separator and the one before the last entry can be changed with `separator` and `last`.
+handlebars_helper!(join: |list: array, { separator: str = ", ", last: str = " and " }| {
+    let items: Vec<String> = list.iter()
+        .map(|item| match item {
+            Value::String(string) => string.clone(),
+            other => other.to_string(),
+        })
+        .collect();
+
+    match items.split_last() {
+        Some((final_item, rest)) if ! rest.is_empty() => format!("{}{}{}", rest.join(separator), last, final_item),
+        Some((final_item, _)) => final_item.clone(),
+        None => String::new(),
+    }
+});
+
+///Escapes a path for use in a url while keeping its slashes.
+pub fn encode_path(path: &str) -> String {
+    utf8_percent_encode(path, PATH_ESCAPES).to_string()
+}
+
+//Escapes a path for use in a url while keeping its slashes, ie `{{url_encode this.link_path}}`.
+handlebars_helper!(url_encode: |path: str| encode_path(path));
+
+//Name of the partial a book's card is rendered with, ie `{{> (card_partial this.book_type) }}`
+// uses `card-pdf` for a PDF.
+handlebars_helper!(card_partial: |book_type: str| format!("card-{}", book_type.to_lowercase()));
+
+//Renders markdown, such as a book's description, to html. Use it with triple braces so the
+// html isn't escaped, ie `{{{markdown this.description}}}`.
+handlebars_helper!(markdown: |text: str| render_markdown(text, false));
+
+
+///Turns a url relative to the root of the site, such as a shelf's or a book's, into a link that
+/// works from the page being rendered, ie `{{link this.url}}` or `{{link @root.bookshelf_directory this.link_path}}`.
+///Parts are joined with slashes and percent-encoded, so urls are kept as plain paths until here and
+/// a `#` in a name stays part of it. An anchor is given with `fragment`, ie `{{link this.url fragment="setup"}}`,
+/// and a url that is only an anchor such as `#tags` leads there on the index page.
+///Pages give the prefix leading to the site root as `site_root`, and with `relative_links` set
+/// directory links get their index page as there's no server to add it.
+fn link(helper: &Helper, _: &Handlebars, context: &Context, _: &mut RenderContext, out: &mut dyn Output) -> HelperResult {
+    let url = helper.params()
+        .iter()
+        .map(|param| match param.value() {
+            Value::String(string) => string.clone(),
+            other => other.to_string(),
+        })
+        .filter(|part| ! part.is_empty())
+        .fold(String::new(), |url, part| {
+            if url.is_empty() {
+                part
+            } else {
+                format!("{}/{}", url.trim_end_matches('/'), part.trim_start_matches('/'))
+            }
+        });
+
+    let root = context.data();
+    let site_root = root.get("site_root").and_then(Value::as_str).unwrap_or("/");
+    let relative = root.get("relative_links").and_then(Value::as_bool).unwrap_or(false);
+
+    let (path, fragment) = match (url.strip_prefix('#'), helper.hash_get("fragment").and_then(|fragment| fragment.value().as_str())) {
+        (Some(anchor), _) => (String::new(), format!("#{}", anchor)),
+        (None, Some(anchor)) => (url, format!("#{}", anchor)),
+        (None, None) => (url, String::new()),
+    };
+
+    let index = if relative && (path.is_empty() || path.ends_with('/')) { "index.html" } else { "" };
+
+    out.write(&html_escape(&format!("{}{}{}{}", site_root, encode_path(&path), index, fragment)))?;
+    Ok(())
+}
+
+
+///Adds the helpers every template can use, on top of the ones handlebars has built in.
+pub fn register_helpers(handlebars: &mut Handlebars) {
+    handlebars.register_helper("date", Box::new(date));
+    handlebars.register_helper("bytes", Box::new(bytes));
+    handlebars.register_helper("pluralize", Box::new(pluralize));
+    handlebars.register_helper("join", Box::new(join));
+    handlebars.register_helper("url_encode", Box::new(url_encode));
+    handlebars.register_helper("markdown", Box::new(markdown));
+    handlebars.register_helper("card_partial", Box::new(card_partial));
+    handlebars.register_helper("link", Box::new(link));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+
+    fn render(template: &str, data: Value) -> String {
+        let mut handlebars = Handlebars::new();
+        register_helpers(&mut handlebars);
+
+        handlebars.render_template(template, &data).unwrap()
+    }
+
+
+    #[test]
+    fn links_start_from_the_site_root() {
+        let data = json!({ "site_root": "/docs/", "relative_links": false, "url": "shelves/Networking/", "dir": "bookshelf", "book": "/guide/" });
+
+        assert_eq!(render("{{link url}}", data.clone()), "/docs/shelves/Networking/");
+        assert_eq!(render("{{link dir book}}", data.clone()), "/docs/bookshelf/guide/");
+        assert_eq!(render(r#"{{link ""}}"#, data), "/docs/");
+    }
+
+
+    #[test]
+    fn relative_links_add_index_pages() {
+        let data = json!({ "site_root": "../../", "relative_links": true, "shelf": "shelves/Networking/", "pdf": "bookshelf/spec.pdf" });
+
+        assert_eq!(render("{{link shelf}}", data.clone()), "../../shelves/Networking/index.html");
+        assert_eq!(render("{{link pdf}}", data.clone()), "../../bookshelf/spec.pdf");
+        assert_eq!(render(r#"{{link ""}}"#, data), "../../index.html");
+    }
+
+
+    #[test]
+    fn fragments_stay_after_the_index_page() {
+        let data = json!({ "site_root": "", "relative_links": true, "url": "#tags", "shelf": "shelves/A/" });
+
+        assert_eq!(render("{{link url}}", data.clone()), "index.html#tags");
+        assert_eq!(render(r#"{{link shelf fragment="books"}}"#, data), "shelves/A/index.html#books");
+    }
+
+
+    #[test]
+    fn links_are_escaped() {
+        let data = json!({ "site_root": "/", "url": "a\"b<c>" });
+
+        assert_eq!(render("{{link url}}", data), "/a%22b%3Cc%3E");
+    }
+
+
+    #[test]
+    fn link_paths_are_percent_encoded() {
+        let data = json!({
+            "site_root": "/docs/", "relative_links": false, "dir": "bookshelf",
+            "shelf": "shelves/C# & .NET/", "pdf": "Specs/100% done?.pdf", "page": "My Book/intro.html",
+            "braces": "{{title}}/",
+        });
+
+        assert_eq!(render("{{link shelf}}", data.clone()), "/docs/shelves/C%23%20&amp;%20.NET/");
+        assert_eq!(render("{{link dir pdf}}", data.clone()), "/docs/bookshelf/Specs/100%25%20done%3F.pdf");
+        assert_eq!(render(r#"{{link dir page fragment="set-up"}}"#, data.clone()), "/docs/bookshelf/My%20Book/intro.html#set-up");
+        assert_eq!(render("{{link braces}}", data), "/docs/%7B%7Btitle%7D%7D/");
+    }
+
+
+    #[test]
+    fn site_roots_are_kept_as_they_are() {
+        let data = json!({ "site_root": "{{path_to_root}}../", "relative_links": true, "url": "shelves/A B/" });
+
+        assert_eq!(render("{{link url}}", data), "{{path_to_root}}../shelves/A%20B/index.html");
+    }
+
+
+    #[test]
+    fn paths_keep_their_slashes() {
+        assert_eq!(encode_path("C# Stuff/100% done?.pdf"), "C%23%20Stuff/100%25%20done%3F.pdf");
+    }
+}
@@ -1,61 +1,290 @@
 use std::fs;
-//use std::io::Read;
-//use std::path::PathBuf;
+use std::path::Path;
 
-use log;
+use anyhow::Context;
 
 use handlebars::Handlebars;
 use serde_json::json;
 
-mod theme;
+pub mod theme;
 use theme::*;
 
-use crate::book::BookshelfMetadata;
+pub mod catalog;
+use catalog::Catalog;
 
+pub mod helpers;
+use helpers::register_helpers;
 
+mod search;
+use search::write_search_index;
 
-//render the index.html file from data and the template
-pub fn render_index(metadata: &BookshelfMetadata) {
+use crate::book::{BookshelfMetadata, HierarchySection, Breadcrumb};
+use crate::config::{Config, RootShelfPosition};
+
+
+///Directory inside the build directory that each shelf's page is written to.
+pub const SHELVES_DIRECTORY: &str = "shelves";
+
+
+///Directory inside the build directory that each tag's virtual shelf page is written to.
+pub const TAGS_DIRECTORY: &str = "tags";
+
+
+///Url of the page listing a shelf, from the shelf's partial path. Like every url the bookshelf
+/// keeps it is a plain path relative to the site root, templates turn it into an encoded link
+/// with the `link` helper.
+pub fn shelf_url(partial_path: &Path) -> String {
+    format!("{}/{}/", SHELVES_DIRECTORY, partial_path.display())
+}
+
+
+///Prefix leading from a page to the site root, for the `link` helper. Relative links climb out of
+/// the page's directory, which is the given number of directories below the build directory.
+pub fn site_root(config: &Config, depth: usize) -> String {
+    if config.bookshelf.relative_links {
+        "../".repeat(depth)
+    } else {
+        config.bookshelf.site_root()
+    }
+}
+
+
+///Url of the page listing every book with a tag, from the directory name the tag was given.
+pub fn tag_url(directory: &str) -> String {
+    format!("{}/{}/", TAGS_DIRECTORY, directory)
+}
+
+
+//register every template and the partials they share, taking any overrides from the theme directory
+fn create_handlebars(theme: &Theme, catalog: &Catalog) -> Handlebars<'static> {
     let mut handlebars = Handlebars::new();
+    register_helpers(&mut handlebars);
+    catalog.register(&mut handlebars);
     
-    if let Result::Err(err) = handlebars.register_template_string("index", &String::from_utf8_lossy(INDEX)) {
-        log::error!("{}", err);
-        panic!("Error registering template. See log.");
+    for (name, partial) in theme.partials() {
+        if let Result::Err(err) = handlebars.register_partial(&name, partial) {
+            log::error!("Partial {}: {}", name, err);
+            panic!("Error registering partial. See log.");
+        }
+    }
+    
+    for (name, (file, _)) in [("index", INDEX), ("shelf", SHELF), ("not_found", NOT_FOUND)] {
+        if let Result::Err(err) = handlebars.register_template_string(name, theme.text(file)) {
+            log::error!("{}: {}", file, err);
+            panic!("Error registering template. See log.");
+        }
+    }
+    
+    handlebars
+}
+
+
+//write the stylesheets, then copy every other file in the theme directory over the top of them
+fn copy_theme_files(theme: &Theme, build_directory: &Path) -> Result<(), anyhow::Error> {
+    for (file, _) in [FUNCTIONAL_STYLESHEET].into_iter().chain(COLOR_STYLESHEETS) {
+        let contents = theme.file(file).unwrap_or_default();
+        
+        fs::write(build_directory.join(file), contents)
+            .with_context(|| format!("Unable to write {}", file))?;
+    }
+    
+    if let Some(directory) = theme.directory() {
+        for asset in theme.assets() {
+            let target = build_directory.join(&asset);
+            
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Unable to create {}", parent.display()))?;
+            }
+            
+            fs::copy(directory.join(&asset), &target)
+                .with_context(|| format!("Unable to copy theme file {}", asset.display()))?;
+        }
     }
     
+    Ok(())
+}
+
+
+//data every page template can use, for a page the given number of directories below the build directory
+fn page_data(config: &Config, metadata: &BookshelfMetadata, depth: usize) -> std::collections::HashMap<&'static str, serde_json::Value> {
     let mut data = std::collections::HashMap::new();
 
     //insert metadata
+    data.insert("site_title",          json!(config.bookshelf.title));
+    data.insert("language",            json!(config.bookshelf.language));
+    data.insert("color_theme",         json!(config.bookshelf.color_theme));
     data.insert("source_directory",    json!(metadata.source_directory));
     data.insert("build_directory",     json!(metadata.build_directory));
     data.insert("bookshelf_directory", json!(metadata.bookshelf_directory));
-    data.insert("hierarchy",    json!(metadata.book_hierarchy));
+    data.insert("shelves_directory",   json!(SHELVES_DIRECTORY));
+    data.insert("languages",           json!(metadata.languages));
+    data.insert("site_root",           json!(site_root(config, depth)));
+    data.insert("relative_links",      json!(config.bookshelf.relative_links));
     
+    data
+}
+
+
+//render a template and write it out, creating any missing directories
+fn write_page(handlebars: &Handlebars, template: &str, data: &std::collections::HashMap<&'static str, serde_json::Value>, path: &Path) {
     log::debug!("Template Data report: {:#?}", data);
-    let file_render = handlebars.render("index", &data)
+    let file_render = handlebars.render(template, data)
                         .expect("Handlebars encountered an error rendering the template from our data.");
     
-    if let Result::Err(err) = 
-      fs::write( format!("{}/{}", metadata.build_directory.display(), "index.html").as_str(), file_render.as_str()) {
+    if let Some(directory) = path.parent() {
+        if let Result::Err(err) = fs::create_dir_all(directory) {
+            log::error!("{}", err);
+        }
+    }
+    
+    if let Result::Err(err) = fs::write(path, file_render.as_str()) {
         log::error!("{}", err);
         panic!("Error building template. See log.");
     }
+}
+
 
+//render the index.html file from data and the template
+pub fn render_index(handlebars: &Handlebars, config: &Config, metadata: &BookshelfMetadata) {
+    let mut data = page_data(config, metadata, 0);
+    
+    data.insert("hierarchy",    json!(metadata.book_hierarchy));
+    data.insert("tag_shelves",  json!(metadata.tag_shelves));
+    data.insert("root_shelf_first",    json!(config.bookshelf.root_shelf_position == RootShelfPosition::First));
+    
+    write_page(handlebars, "index", &data, &metadata.build_directory.join("index.html"));
+}
+
+
+//render a page for the shelf and then for every shelf below it
+pub fn render_shelf(handlebars: &Handlebars, config: &Config, metadata: &BookshelfMetadata, 
+                    shelf: &HierarchySection, breadcrumbs: &mut Vec<Breadcrumb>) {
+    let depth = 1 + shelf.partial_path.components().count();
+    let mut data = page_data(config, metadata, depth);
+    
+    data.insert("page_title",  json!(shelf.name));
+    data.insert("shelf",       json!(shelf));
+    data.insert("breadcrumbs", json!(breadcrumbs));
+    
+    //the shelf's url is where its page goes, which differs from its partial path when slugged
+    let page_path = metadata.build_directory.join(&shelf.url).join("index.html");
+    write_page(handlebars, "shelf", &data, &page_path);
+    
+    breadcrumbs.push( Breadcrumb { name: shelf.name.clone(), url: shelf.url.clone() } );
+    for sub_section in &shelf.sub_sections {
+        render_shelf(handlebars, config, metadata, sub_section, breadcrumbs);
+    }
+    breadcrumbs.pop();
 }
 
 
-pub fn build_pages(data: BookshelfMetadata) {
+//render the page of a tag's virtual shelf
+pub fn render_tag_shelf(handlebars: &Handlebars, config: &Config, metadata: &BookshelfMetadata, 
+                        tag_shelf: &HierarchySection, breadcrumbs: &[Breadcrumb]) {
+    let mut data = page_data(config, metadata, 2);
+    
+    data.insert("page_title",  json!(tag_shelf.name));
+    data.insert("shelf",       json!(tag_shelf));
+    data.insert("breadcrumbs", json!(breadcrumbs));
+    
+    let page_path = metadata.build_directory.join(&tag_shelf.url).join("index.html");
+    write_page(handlebars, "shelf", &data, &page_path);
+}
+
+
+//every book on the shelves, suggested by the not found page when its name is close to the address asked for
+fn collect_book_links(section: &HierarchySection, bookshelf_directory: &Path, links: &mut Vec<Breadcrumb>) {
+    for book in section.books.iter().chain(section.series.iter().flat_map(|series| series.books.iter())) {
+        links.push( Breadcrumb {
+            name: book.title.clone(),
+            url: format!("{}/{}", bookshelf_directory.display(), book.link_path.display()),
+        });
+    }
+    
+    for sub_section in &section.sub_sections {
+        collect_book_links(sub_section, bookshelf_directory, links);
+    }
+}
+
+
+//render the page shown for missing addresses
+pub fn render_not_found(handlebars: &Handlebars, config: &Config, metadata: &BookshelfMetadata, catalog: &Catalog) {
+    let mut data = page_data(config, metadata, 0);
+    
+    //the page is served in place of any missing address, so its links can only start from the site root
+    data.insert("site_root",      json!(config.bookshelf.site_root()));
+    data.insert("relative_links", json!(false));
+    
+    let mut books = vec!();
+    collect_book_links(&metadata.book_hierarchy, &metadata.bookshelf_directory, &mut books);
+    
+    data.insert("page_title", json!(catalog.translate("not-found", &[])));
+    data.insert("books",      json!(books));
+    
+    write_page(handlebars, "not_found", &data, &metadata.build_directory.join(&config.bookshelf.not_found_page));
+}
+
+
+pub fn build_pages(config: &Config, data: BookshelfMetadata, theme: &Theme, catalog: &Catalog) {
     
     
     //copy files over
-    for (filename, file_data) in vec!(FUNCTIONAL_STYLESHEET, DARK_STYLESHEET) {
-        if let Result::Err(err) = 
-          fs::write( format!("{}", data.build_directory.join(filename).display()).as_str(), file_data ) {
-            log::error!("{}", err);
-            panic!("Error copying files into build directory. See log.");
-        }
+    if let Result::Err(err) = copy_theme_files(theme, &data.build_directory) {
+        log::error!("{:#}", err);
+        panic!("Error copying files into build directory. See log.");
+    }
+    
+    //the index page searches every shelf, book and mdBook section
+    if let Result::Err(err) = write_search_index(config, &data, catalog) {
+        log::error!("{:#}", err);
+        panic!("Error writing the search index. See log.");
     }
     
     //process template files to build pages
-    render_index(&data);
+    let handlebars = create_handlebars(theme, catalog);
+    
+    render_index(&handlebars, config, &data);
+    
+    //every shelf gets its own page, with a trail of links back up to the index
+    let mut breadcrumbs = vec!( Breadcrumb { name: config.bookshelf.title.clone(), url: String::new() } );
+    for shelf in &data.book_hierarchy.sub_sections {
+        render_shelf(&handlebars, config, &data, shelf, &mut breadcrumbs);
+    }
+    
+    render_not_found(&handlebars, config, &data, catalog);
+    
+    //tag pages lead back to the tag view of the index
+    breadcrumbs.push( Breadcrumb { name: catalog.translate("tags", &[]), url: "#tags".to_string() } );
+    for tag_shelf in &data.tag_shelves {
+        render_tag_shelf(&handlebars, config, &data, tag_shelf, &breadcrumbs);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn shelf_links_encode_reserved_characters() {
+        let handlebars = create_handlebars(&Theme::default(), &Catalog::default());
+
+        let shelf = HierarchySection {
+            name: "C# & .NET".to_string(),
+            url: shelf_url(Path::new("C# & .NET")),
+            sub_sections: vec!( HierarchySection {
+                name: "100% Done?".to_string(),
+                url: shelf_url(Path::new("C# & .NET/100% Done?")),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let rendered = handlebars.render_template("{{> shelf_summary }}", &json!(shelf)).unwrap();
+
+        assert!(rendered.contains(r#"href="/shelves/C%23%20&amp;%20.NET/">C# &amp; .NET</a>"#));
+        assert!(rendered.contains(r#"href="/shelves/C%23%20&amp;%20.NET/100%25%20Done%3F/">100% Done?</a>"#));
+    }
 }
@@ -0,0 +1,240 @@
+use std::{
+    fs,
+    path::Path,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::book::{BookMetadata, BookType, BookshelfMetadata, HierarchySection};
+use crate::book_builder::page_texts;
+use crate::config::Config;
+
+use super::catalog::Catalog;
+use super::helpers::encode_path;
+
+
+///Name of the file holding the shelf wide search index, for tools that want to read it.
+pub const SEARCH_INDEX_FILE: &str = "search_index.json";
+
+///Same index as a script, since pages opened from disk can't fetch the json.
+pub const SEARCH_INDEX_SCRIPT: &str = "search_index.js";
+
+///Name of the search index mdBook writes into a book's html output.
+const MDBOOK_SEARCH_INDEX: &str = "searchindex.json";
+
+
+///Something visitors can find from the index page, a shelf, a book, a section of an mdBook or
+/// a page of a PDF.
+#[derive(Debug, Serialize)]
+pub struct SearchEntry {
+    pub kind: &'static str,
+    pub title: String,
+    ///where the entry sits, ie the book and chapters above a section
+    pub trail: Vec<String>,
+    ///relative to the site root, like every url the bookshelf keeps
+    pub url: String,
+    pub language: Option<String>,
+    pub body: String,
+}
+
+
+///The parts of an mdBook's search index the shelf wide one is made from.
+#[derive(Deserialize)]
+struct MDBookSearchIndex {
+    doc_urls: Vec<String>,
+    index: MDBookIndex,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MDBookIndex {
+    document_store: MDBookDocumentStore,
+}
+
+#[derive(Deserialize)]
+struct MDBookDocumentStore {
+    docs: std::collections::HashMap<String, MDBookDocument>,
+}
+
+#[derive(Deserialize)]
+struct MDBookDocument {
+    id: String,
+    title: String,
+    body: String,
+    breadcrumbs: String,
+}
+
+
+///Reads the sections of an mdBook from the search index mdBook wrote for it. The book's html
+/// output is found at the path, relative to the build directory and so the site root.
+fn mdbook_sections(build_directory: &Path, book_path: &str, book_title: &str, language: Option<&String>) -> Vec<SearchEntry> {
+    let index_path = build_directory.join(book_path).join(MDBOOK_SEARCH_INDEX);
+    let book_url = encode_path(book_path);
+
+    //books with search turned off, or without html output, have no index
+    let raw = match fs::read_to_string(&index_path) {
+        Ok(raw) => raw,
+        Err(_) => {
+            log::debug!("> No search index at {}", index_path.display());
+            return vec!();
+        },
+    };
+
+    let index: MDBookSearchIndex = match serde_json::from_str(&raw) {
+        Ok(index) => index,
+        Err(err) => {
+            log::warn!("Unable to read the search index of \"{}\": {}", book_title, err);
+            return vec!();
+        },
+    };
+
+    let mut documents: Vec<MDBookDocument> = index.index.document_store.docs.into_values().collect();
+    documents.sort_by_key(|document| document.id.parse::<usize>().unwrap_or(usize::MAX));
+
+    documents.into_iter()
+        .filter_map(|document| {
+            let doc_url = index.doc_urls.get(document.id.parse::<usize>().ok()?)?;
+            let (page, anchor) = doc_url.split_once('#').unwrap_or((doc_url, ""));
+            let anchor = if anchor.is_empty() { String::new() } else { format!("#{}", anchor) };
+
+            //the section itself ends the breadcrumbs, and a chapter's first heading usually repeats
+            // its name, so neither is repeated in the trail
+            let mut trail: Vec<String> = vec!(book_title.to_string());
+            trail.extend(document.breadcrumbs.split(" » ").map(ToString::to_string));
+            trail.pop();
+            if trail.len() > 1 && trail.last() == Some(&document.title) {
+                trail.pop();
+            }
+
+            Some( SearchEntry {
+                kind: "section",
+                title: document.title,
+                trail,
+                url: format!("{}{}{}", book_url, encode_path(page), anchor),
+                language: language.cloned(),
+                body: document.body,
+            })
+        })
+        .collect()
+}
+
+
+///Reads the text of a PDF page by page. Hits open the PDF at the page, which browsers' viewers
+/// understand from the `#page=` fragment.
+fn pdf_pages(build_directory: &Path, pdf_path: &str, book_title: &str, language: Option<&String>, catalog: &Catalog) -> Vec<SearchEntry> {
+    let pages = match page_texts(&build_directory.join(pdf_path)) {
+        Some(pages) => pages,
+        None => return vec!(),
+    };
+
+    if pages.iter().all(String::is_empty) {
+        log::info!("> \"{}\" has no text to search, scanned PDFs need to be run through OCR first.", book_title);
+    }
+
+    pages.into_iter()
+        .enumerate()
+        .filter(|(_, text)| ! text.is_empty())
+        .map(|(index, text)| SearchEntry {
+            kind: "page",
+            title: catalog.translate("page-number", &[(index + 1).to_string()]),
+            trail: vec!(book_title.to_string()),
+            url: format!("{}#page={}", encode_path(pdf_path), index + 1),
+            language: language.cloned(),
+            body: text,
+        })
+        .collect()
+}
+
+
+///Everything the search index is made from.
+struct Sources<'a> {
+    metadata: &'a BookshelfMetadata,
+    catalog: &'a Catalog,
+    search_pdf_text: bool,
+}
+
+
+///Adds a book, and every section or page of every translation of it.
+fn book_entries(book: &BookMetadata, shelves: &[String], sources: &Sources, entries: &mut Vec<SearchEntry>) {
+    let metadata = sources.metadata;
+    let book_path = format!("{}/{}", metadata.bookshelf_directory.display(), book.link_path.display());
+
+    let mut body = vec!(book.description.clone());
+    body.extend(book.authors.iter().cloned());
+    body.extend(book.tags.iter().cloned());
+
+    entries.push( SearchEntry {
+        kind: "book",
+        title: book.title.clone(),
+        trail: shelves.to_vec(),
+        url: encode_path(&book_path),
+        language: book.language.clone(),
+        body: body.join(" "),
+    });
+
+    //every translation is searchable, not just the one the card opens
+    let contents: Vec<(&str, Option<&String>)> = match &book.translation {
+        Some(translation) => translation.languages.iter().map(|language| (language.url.as_str(), Some(&language.name))).collect(),
+        None => vec!( (book_path.as_str(), book.language.as_ref()) ),
+    };
+
+    for (path, language) in contents {
+        match book.book_type {
+            BookType::MDBook => entries.extend(mdbook_sections(&metadata.build_directory, path, &book.title, language)),
+            BookType::PDF if sources.search_pdf_text => entries.extend(pdf_pages(&metadata.build_directory, path, &book.title, language, sources.catalog)),
+            BookType::PDF => (),
+        }
+    }
+}
+
+
+///Adds a shelf, its books and everything below it.
+fn shelf_entries(section: &HierarchySection, shelves: &mut Vec<String>, sources: &Sources, entries: &mut Vec<SearchEntry>) {
+    let is_root = section.partial_path.as_os_str().is_empty();
+
+    if ! is_root {
+        entries.push( SearchEntry {
+            kind: "shelf",
+            title: section.name.clone(),
+            trail: shelves.clone(),
+            url: encode_path(&section.url),
+            language: None,
+            body: String::new(),
+        });
+        shelves.push(section.name.clone());
+    }
+
+    for book in section.books.iter().chain(section.series.iter().flat_map(|series| series.books.iter())) {
+        book_entries(book, shelves, sources, entries);
+    }
+
+    for sub_section in &section.sub_sections {
+        shelf_entries(sub_section, shelves, sources, entries);
+    }
+
+    if ! is_root {
+        shelves.pop();
+    }
+}
+
+
+///Merges the search index of every mdBook and the text of every PDF, along with every shelf and
+/// book, into one index the index page searches without a server. It is written both as json and as a script.
+pub fn write_search_index(config: &Config, metadata: &BookshelfMetadata, catalog: &Catalog) -> Result<(), anyhow::Error> {
+    let sources = Sources { metadata, catalog, search_pdf_text: config.bookshelf.search_pdf_text };
+    
+    let mut entries = vec!();
+    shelf_entries(&metadata.book_hierarchy, &mut vec!(), &sources, &mut entries);
+
+    let json = serde_json::to_string(&entries)?;
+
+    fs::write(metadata.build_directory.join(SEARCH_INDEX_FILE), &json)
+        .with_context(|| format!("Unable to write {}", SEARCH_INDEX_FILE))?;
+    fs::write(metadata.build_directory.join(SEARCH_INDEX_SCRIPT), format!("window.bookshelfSearchIndex = {};", json))
+        .with_context(|| format!("Unable to write {}", SEARCH_INDEX_SCRIPT))?;
+
+    log::info!("Search index has {} entries", entries.len());
+
+    Ok(())
+}
@@ -1,7 +1,166 @@
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use walkdir::WalkDir;
+
+use crate::config::Config;
+
+
 //load in files so they are embeded into the binary.
+//Each is kept with its path inside a theme directory so a project theme can override it.
 //>Templates
-pub static INDEX: &[u8] = include_bytes!("index.hbs");
+pub static INDEX: (&str, &[u8]) = ("index.hbs", include_bytes!("index.hbs"));
+pub static SHELF: (&str, &[u8]) = ("shelf.hbs", include_bytes!("shelf.hbs"));
+pub static BOOK_NAV: (&str, &[u8]) = ("book_nav.hbs", include_bytes!("book_nav.hbs"));
+pub static NOT_FOUND: (&str, &[u8]) = ("404.hbs", include_bytes!("404.hbs"));
+
+//>Partials shared between templates, registered under their path inside the partials directory
+pub const PARTIALS_DIRECTORY: &str = "partials";
+pub static PARTIALS: [(&str, &[u8]); 9] = [
+    ("partials/page_head.hbs", include_bytes!("partials/page_head.hbs")),
+    ("partials/theme_toggle.hbs", include_bytes!("partials/theme_toggle.hbs")),
+    ("partials/search.hbs", include_bytes!("partials/search.hbs")),
+    ("partials/card-mdbook.hbs", include_bytes!("partials/card-mdbook.hbs")),
+    ("partials/card-pdf.hbs", include_bytes!("partials/card-pdf.hbs")),
+    ("partials/card_groups.hbs", include_bytes!("partials/card_groups.hbs")),
+    ("partials/language_preference.hbs", include_bytes!("partials/language_preference.hbs")),
+    ("partials/shelf_books.hbs", include_bytes!("partials/shelf_books.hbs")),
+    ("partials/shelf_summary.hbs", include_bytes!("partials/shelf_summary.hbs")),
+];
+
+//>Catalogs of the strings shown on pages, named by language
+pub const CATALOGS_DIRECTORY: &str = "catalogs";
+pub static CATALOGS: [(&str, &[u8]); 2] = [
+    ("catalogs/en.toml", include_bytes!("catalogs/en.toml")),
+    ("catalogs/de.toml", include_bytes!("catalogs/de.toml")),
+];
 
 //>files (CSS images etc)
 pub static FUNCTIONAL_STYLESHEET: (&str, &[u8]) = ("functional.css", include_bytes!("functional.css") );
-pub static DARK_STYLESHEET: (&str, &[u8]) = ("style_dark.css", include_bytes!("style_dark.css") );
+pub static COLOR_STYLESHEETS: [(&str, &[u8]); 3] = [
+    ("style_light.css", include_bytes!("style_light.css")),
+    ("style_dark.css", include_bytes!("style_dark.css")),
+    ("style_high_contrast.css", include_bytes!("style_high_contrast.css")),
+];
+
+
+///Every file of the default theme, by its path inside a theme directory.
+pub fn default_files() -> Vec<(&'static str, &'static [u8])> {
+    [INDEX, SHELF, BOOK_NAV, NOT_FOUND].into_iter()
+        .chain(PARTIALS)
+        .chain(CATALOGS)
+        .chain([FUNCTIONAL_STYLESHEET])
+        .chain(COLOR_STYLESHEETS)
+        .collect()
+}
+
+
+///The default theme with any files from the project's theme directory laid over it.
+#[derive(Debug, Default)]
+pub struct Theme {
+    directory: Option<PathBuf>,
+}
+
+
+impl Theme {
+    ///Uses the theme directory from the config when the project has one.
+    pub fn new(config: &Config) -> Theme {
+        let directory = config.root.join(&config.bookshelf.theme_directory);
+
+        if directory.is_dir() {
+            log::debug!("Using theme files from {}", directory.display());
+            Theme { directory: Some(directory) }
+        } else {
+            Theme::default()
+        }
+    }
+
+
+    ///The project's theme directory, if it has one.
+    pub fn directory(&self) -> Option<&Path> {
+        self.directory.as_deref()
+    }
+
+
+    ///Contents of a theme file, from the theme directory if it overrides it, otherwise the default.
+    ///Returns None for a file that is in neither.
+    pub fn file(&self, name: &str) -> Option<Cow<'static, [u8]>> {
+        if let Some(directory) = &self.directory {
+            let path = directory.join(name);
+
+            if path.is_file() {
+                match fs::read(&path) {
+                    Ok(contents) => return Some(Cow::Owned(contents)),
+                    Err(err) => log::error!("Unable to read theme file {}, using the default: {}", path.display(), err),
+                }
+            }
+        }
+
+        default_files().into_iter()
+                       .find(|(default, _)| *default == name)
+                       .map(|(_, contents)| Cow::Borrowed(contents))
+    }
+
+
+    ///Contents of a theme file as text, such as a template.
+    pub fn text(&self, name: &str) -> String {
+        self.file(name)
+            .map(|contents| String::from_utf8_lossy(&contents).to_string())
+            .unwrap_or_default()
+    }
+
+
+    ///Every partial by the name templates use it under, which is its path inside the partials
+    /// directory without the extension, ie `cards/pdf`. Any partial in the theme directory is
+    /// included, whether it overrides a default one or adds a new one.
+    pub fn partials(&self) -> Vec<(String, String)> {
+        let mut files: Vec<String> = PARTIALS.iter().map(|(file, _)| file.to_string()).collect();
+        
+        if let Some(directory) = &self.directory {
+            let partials_path = directory.join(PARTIALS_DIRECTORY);
+            
+            for entry in WalkDir::new(&partials_path).into_iter().filter_map(Result::ok) {
+                let is_partial = entry.file_type().is_file() && entry.path().extension().map(|extension| extension == "hbs").unwrap_or(false);
+                
+                if let (true, Ok(file)) = (is_partial, entry.path().strip_prefix(directory)) {
+                    let file = file.to_string_lossy().replace('\\', "/");
+                    if ! files.contains(&file) {
+                        files.push(file);
+                    }
+                }
+            }
+        }
+        
+        files.into_iter()
+             .map(|file| {
+                 let name = file.trim_start_matches(&format!("{}/", PARTIALS_DIRECTORY))
+                                .trim_end_matches(".hbs")
+                                .to_string();
+                 let partial = self.text(&file);
+                 (name, partial)
+             })
+             .collect()
+    }
+
+
+    ///Files in the theme directory that are copied into the build directory as they are, such as
+    /// stylesheets and images, by their path inside the theme directory. Templates and catalogs
+    /// are used while building so they are left out.
+    pub fn assets(&self) -> Vec<PathBuf> {
+        let directory = match &self.directory {
+            Some(directory) => directory,
+            None => return vec!(),
+        };
+
+        WalkDir::new(directory).into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file())
+            .filter_map(|entry| entry.path().strip_prefix(directory).ok().map(Path::to_path_buf))
+            .filter(|path| path.extension().map(|extension| extension != "hbs").unwrap_or(true))
+            .filter(|path| ! path.starts_with(CATALOGS_DIRECTORY))
+            .collect()
+    }
+}